@@ -0,0 +1,219 @@
+use std::io::{self, IsTerminal};
+
+use crossterm::style::{Color, Stylize};
+
+use crate::interpreter::RuntimeError;
+use crate::parser::SyntaxError;
+use crate::tokenizer::token::{Span, Token};
+
+/// An error which can be reported against the source text it came from.
+pub trait Diagnose {
+    /// One-line summary of the problem.
+    fn headline(&self) -> String;
+
+    fn span(&self) -> Span;
+
+    /// Short note printed under the offending text.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
+    /// Suggestion for fixing the problem.
+    fn hint(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl Diagnose for SyntaxError {
+    fn headline(&self) -> String {
+        self.description()
+    }
+
+    fn span(&self) -> Span {
+        SyntaxError::span(self)
+    }
+
+    fn label(&self) -> Option<String> {
+        match self.expected() {
+            [] => None,
+            expected => Some(format!("expected {}", expected.join(" or ")))
+        }
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match self {
+            SyntaxError::UnterminatedString(_)
+                => Some("add a closing quote to the end of the string"),
+            SyntaxError::UnknownCharacter(_, _)
+                => Some("quote the text if the character is meant to be part of an argument"),
+            SyntaxError::BadSubstitution(_)
+                => Some("write `${name}`, where the name is made of letters, digits and underscores"),
+            SyntaxError::UnterminatedHereDoc(_, _)
+                => Some("end the here-document with a line holding only its delimiter"),
+            SyntaxError::UnexpectedToken(Token::Keyword(_), expected, _) if expected.contains(&"identifier")
+                => Some("keywords can't be used as names"),
+            SyntaxError::UnexpectedEOF(expected, _) if expected.contains(&"`)`") || expected.contains(&"`}`")
+                => Some("check for an unclosed bracket"),
+            _ => None
+        }
+    }
+}
+
+impl Diagnose for RuntimeError {
+    fn headline(&self) -> String {
+        self.to_string()
+    }
+
+    fn span(&self) -> Span {
+        RuntimeError::span(self)
+    }
+
+    fn hint(&self) -> Option<&'static str> {
+        match self {
+            RuntimeError::UndefinedVariable(_, _)
+                => Some("define it first with `let name = value`"),
+            RuntimeError::CommandFailed(_, e, _) if e.kind() == io::ErrorKind::NotFound
+                => Some("check the spelling, and that the program is in a directory listed in $PATH"),
+            _ => None
+        }
+    }
+}
+
+/// Renders diagnostics in the style of rustc: a headline, the offending
+/// source line, a caret underline and any hint.
+pub struct Renderer<'a> {
+    source: &'a str,
+    color: bool
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(source: &'a str, color: bool) -> Self {
+        Renderer {
+            source,
+            color
+        }
+    }
+
+    /// A renderer which colors its output if stderr is a terminal.
+    pub fn for_stderr(source: &'a str) -> Self {
+        Self::new(source, io::stderr().is_terminal())
+    }
+
+    pub fn render(&self, severity: &str, diagnostic: &dyn Diagnose) -> String {
+        let span = diagnostic.span();
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let mut out = format!(
+            "{}{}\n",
+            self.paint(&format!("{}: ", severity), Color::Red),
+            self.bold(&diagnostic.headline())
+        );
+        out += &format!("{}{} {}:{}\n", gutter, self.paint("-->", Color::Blue), span.line, span.column);
+
+        if let Some(line) = self.source.lines().nth(span.line.saturating_sub(1)) {
+            let pipe = self.paint("|", Color::Blue);
+            out += &format!("{} {}\n", gutter, pipe);
+            out += &format!("{} {} {}\n", self.paint(&line_number, Color::Blue), pipe, line);
+
+            // Reuse tabs from the source line so the carets stay aligned
+            let prefix: String = line
+                .chars()
+                .take(span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let line_end = self.source[span.start.min(self.source.len())..]
+                .find('\n')
+                .map_or(self.source.len(), |offset| span.start + offset);
+            let width = self.source
+                .get(span.start..span.end.min(line_end))
+                .map_or(0, |text| text.trim_end().chars().count())
+                .max(1);
+            let mut underline = "^".repeat(width);
+            if let Some(label) = diagnostic.label() {
+                underline = format!("{} {}", underline, label);
+            }
+            out += &format!("{} {} {}{}\n", gutter, pipe, prefix, self.paint(&underline, Color::Red));
+        }
+
+        if let Some(hint) = diagnostic.hint() {
+            out += &format!("{} {} {}\n", gutter, self.paint("=", Color::Blue), self.bold("hint:") + " " + hint);
+        }
+        out
+    }
+
+    fn paint(&self, text: &str, color: Color) -> String {
+        if self.color {
+            text.with(color).bold().to_string()
+        } else {
+            String::from(text)
+        }
+    }
+
+    fn bold(&self, text: &str) -> String {
+        if self.color {
+            text.bold().to_string()
+        } else {
+            String::from(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn diagnostics_test_1() {
+        let source = "let x = 1\nlet if = 4\n";
+        let e = Parser::new().parse(source).unwrap_err();
+        assert_eq!(Renderer::new(source, false).render("syntax error", &e), "\
+syntax error: Unexpected keyword `if`
+ --> 2:5
+  |
+2 | let if = 4
+  |     ^^ expected identifier
+  = hint: keywords can't be used as names
+");
+    }
+
+    #[test]
+    fn diagnostics_test_2() {
+        let source = "\techo \"oops";
+        let e = Parser::new().parse(source).unwrap_err();
+        assert_eq!(Renderer::new(source, false).render("syntax error", &e), "\
+syntax error: Unterminated string literal
+ --> 1:7
+  |
+1 | \techo \"oops
+  | \t     ^^^^^
+  = hint: add a closing quote to the end of the string
+");
+    }
+
+    #[test]
+    fn diagnostics_test_3() {
+        // Punctuation is only quoted, rather than named as well
+        let descriptions: Vec<String> = ["let x = {", "echo a >\nb", "1 + 2)"]
+            .iter()
+            .map(|source| Parser::new().parse(source).unwrap_err().description())
+            .collect();
+        assert_eq!(descriptions, vec!["Unexpected `{`", "Unexpected newline", "Unexpected `)`"]);
+    }
+
+    #[test]
+    fn diagnostics_test_4() {
+        let source = "echo hi\nrushfoo --help\n";
+        let span = Span { start: 8, end: 22, line: 2, column: 1 };
+        let e = RuntimeError::CommandFailed(String::from("rushfoo"), io::Error::from(io::ErrorKind::NotFound), span);
+        assert_eq!(Renderer::new(source, false).render("error", &e), "\
+error: rushfoo: command not found
+ --> 2:1
+  |
+2 | rushfoo --help
+  | ^^^^^^^^^^^^^^
+  = hint: check the spelling, and that the program is in a directory listed in $PATH
+");
+    }
+}
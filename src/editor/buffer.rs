@@ -0,0 +1,260 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text of the line being edited and the cursor, a byte offset which is
+/// always on a grapheme boundary, with emacs-style editing operations.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    text: String,
+    cursor: usize,
+    /// The text removed by the last kill, which a yank puts back.
+    killed: String,
+    /// Whether the last operation was a kill, so the next one adds to it.
+    killing: bool
+}
+
+/// Letters and digits make up words for moving by word, as in emacs.
+fn is_alphanumeric(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+/// Anything but whitespace makes up words for Ctrl-W, as in a terminal.
+fn is_not_whitespace(grapheme: &str) -> bool {
+    !grapheme.chars().all(char::is_whitespace)
+}
+
+impl LineBuffer {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replaces the text, leaving the cursor at its end. Killed text is kept.
+    pub fn set(&mut self, text: &str) {
+        self.text = String::from(text);
+        self.cursor = self.text.len();
+        self.killing = false;
+    }
+
+    /// Replaces the text in `range` with `text`, leaving the cursor after it.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        self.text.replace_range(range.clone(), text);
+        self.cursor = range.start + text.len();
+        self.killing = false;
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+        self.killing = false;
+    }
+
+    /// The start of the grapheme before the cursor.
+    fn previous_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    /// The end of the grapheme after the cursor.
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    /// The start of the word before the cursor, skipping any other text
+    /// between the two first.
+    fn word_start(&self, is_word: fn(&str) -> bool) -> usize {
+        let mut start = self.cursor;
+        let mut in_word = false;
+        for (index, grapheme) in self.text[..self.cursor].grapheme_indices(true).rev() {
+            if is_word(grapheme) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            start = index;
+        }
+        start
+    }
+
+    /// The end of the word after the cursor, skipping any other text
+    /// between the two first.
+    fn word_end(&self, is_word: fn(&str) -> bool) -> usize {
+        let mut in_word = false;
+        for (index, grapheme) in self.text[self.cursor..].grapheme_indices(true) {
+            if is_word(grapheme) {
+                in_word = true;
+            } else if in_word {
+                return self.cursor + index
+            }
+        }
+        self.text.len()
+    }
+
+    /// Moves the cursor to `position`, which must be a grapheme boundary.
+    pub fn move_to(&mut self, position: usize) {
+        self.cursor = position;
+        self.killing = false;
+    }
+
+    pub fn move_left(&mut self) {
+        self.move_to(self.previous_boundary());
+    }
+
+    pub fn move_right(&mut self) {
+        self.move_to(self.next_boundary());
+    }
+
+    pub fn move_start(&mut self) {
+        self.move_to(0);
+    }
+
+    pub fn move_end(&mut self) {
+        self.move_to(self.text.len());
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.move_to(self.word_start(is_alphanumeric));
+    }
+
+    pub fn move_word_right(&mut self) {
+        self.move_to(self.word_end(is_alphanumeric));
+    }
+
+    /// Deletes the grapheme before the cursor, returning false if there isn't one.
+    pub fn delete_back(&mut self) -> bool {
+        let start = self.previous_boundary();
+        self.killing = false;
+        self.text.replace_range(start..self.cursor, "");
+        std::mem::replace(&mut self.cursor, start) != start
+    }
+
+    /// Deletes the grapheme after the cursor, returning false if there isn't one.
+    pub fn delete_forward(&mut self) -> bool {
+        let end = self.next_boundary();
+        self.killing = false;
+        self.text.replace_range(self.cursor..end, "");
+        end != self.cursor
+    }
+
+    /// Removes `range` from the text into the killed text. Consecutive kills
+    /// are joined, so they can be yanked back together.
+    fn kill(&mut self, range: Range<usize>) {
+        let removed: String = self.text.drain(range.clone()).collect();
+        if !self.killing {
+            self.killed.clear();
+        }
+        if range.start < self.cursor {
+            self.killed.insert_str(0, &removed);
+        } else {
+            self.killed.push_str(&removed);
+        }
+        self.cursor = range.start;
+        self.killing = true;
+    }
+
+    /// Ctrl-U: kills the text before the cursor.
+    pub fn kill_to_start(&mut self) {
+        self.kill(0..self.cursor);
+    }
+
+    /// Ctrl-K: kills the text after the cursor.
+    pub fn kill_to_end(&mut self) {
+        self.kill(self.cursor..self.text.len());
+    }
+
+    /// Ctrl-W: kills the whitespace-separated word before the cursor.
+    pub fn kill_word_back(&mut self) {
+        self.kill(self.word_start(is_not_whitespace)..self.cursor);
+    }
+
+    /// Alt-D: kills the word after the cursor.
+    pub fn kill_word_forward(&mut self) {
+        self.kill(self.cursor..self.word_end(is_alphanumeric));
+    }
+
+    /// Ctrl-Y: inserts the last killed text at the cursor.
+    pub fn yank(&mut self) {
+        let killed = self.killed.clone();
+        self.insert(&killed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str, cursor: usize) -> LineBuffer {
+        let mut buffer = LineBuffer::default();
+        buffer.set(text);
+        buffer.cursor = cursor;
+        buffer
+    }
+
+    #[test]
+    fn buffer_test_1() {
+        // "e" and a combining accent make one grapheme, as do the flag's two characters
+        let mut b = buffer("ae\u{301}🇳🇿漢", 0);
+        b.move_right();
+        b.move_right();
+        assert_eq!(b.cursor(), 4);
+        b.move_right();
+        assert_eq!(b.cursor(), 12);
+        assert!(b.delete_forward());
+        assert_eq!(b.text(), "ae\u{301}🇳🇿");
+        assert!(!b.delete_forward());
+        assert!(b.delete_back());
+        assert_eq!(b.text(), "ae\u{301}");
+        b.move_start();
+        assert!(!b.delete_back());
+        b.insert("x");
+        assert_eq!((b.text(), b.cursor()), ("xae\u{301}", 1));
+    }
+
+    #[test]
+    fn buffer_test_2() {
+        let mut b = buffer("git commit --amend", 18);
+        b.move_word_left();
+        assert_eq!(b.cursor(), 13);
+        b.move_word_left();
+        assert_eq!(b.cursor(), 4);
+        b.move_word_right();
+        assert_eq!(b.cursor(), 10);
+        b.move_end();
+        b.move_word_right();
+        assert_eq!(b.cursor(), 18);
+    }
+
+    #[test]
+    fn buffer_test_3() {
+        let mut b = buffer("echo one two  ", 14);
+        b.kill_word_back();
+        b.kill_word_back();
+        assert_eq!(b.text(), "echo ");
+        b.yank();
+        assert_eq!(b.text(), "echo one two  ");
+
+        b.move_start();
+        b.kill_word_forward();
+        b.kill_to_end();
+        assert_eq!((b.text(), b.cursor()), ("", 0));
+        b.yank();
+        assert_eq!(b.text(), "echo one two  ");
+
+        // Moving in between starts a fresh kill
+        b.move_to(8);
+        b.kill_to_end();
+        b.move_left();
+        b.kill_to_start();
+        b.yank();
+        assert_eq!(b.text(), "echo one");
+    }
+}
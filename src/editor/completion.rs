@@ -0,0 +1,339 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::tokenizer::is_operator_char;
+
+/// The names the shell knows about, which complete along with programs and
+/// files.
+#[derive(Debug, Default)]
+pub struct Names {
+    /// Builtins and functions, which complete in command position.
+    pub commands: Vec<String>,
+    pub variables: Vec<String>,
+    /// The shell's `$PATH`, whose programs complete in command position.
+    pub path: String
+}
+
+/// What completing the word before the cursor does.
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    /// Where the text to replace starts. It ends at the cursor.
+    pub start: usize,
+    /// The text to replace it with, quoted as needed: what all the
+    /// candidates start with, and the rest of the word if there's just one.
+    pub replacement: String,
+    /// The candidates as they're listed when there's more than one.
+    pub candidates: Vec<String>
+}
+
+/// The word the cursor is in, as far as the cursor.
+#[derive(Debug, PartialEq)]
+struct Word {
+    start: usize,
+    /// The word's text with quotes and escapes removed.
+    text: String,
+    /// The quote the word is still inside of, if any.
+    quote: Option<char>,
+    /// Whether the word names the command to run.
+    command: bool
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Reads the words of `line` up to `cursor`, as the tokenizer would, to
+/// find the last one and whether it's in command position: at the start,
+/// or after an operator such as `|` or `;`, or after `if`.
+fn word_at(line: &str, cursor: usize) -> Word {
+    let mut word: Option<Word> = None;
+    let mut command = true;
+    let mut chars = line[..cursor].char_indices();
+    while let Some((index, c)) = chars.next() {
+        if let Some(Word { quote: quote @ Some(_), text, .. }) = &mut word {
+            if *quote == Some(c) {
+                *quote = None;
+            } else {
+                text.push(c);
+            }
+            continue
+        }
+        if c.is_whitespace() || is_operator_char(c) {
+            if let Some(word) = word.take() {
+                command = word.command && word.text == "if";
+            }
+            if is_operator_char(c) {
+                // The word after a redirection is a file
+                command = !matches!(c, '<' | '>');
+            }
+            continue
+        }
+        let word = word.get_or_insert(Word { start: index, text: String::new(), quote: None, command });
+        match c {
+            '\'' | '"' => word.quote = Some(c),
+            '\\' => if let Some((_, escaped)) = chars.next() {
+                word.text.push(escaped);
+            },
+            c => word.text.push(c)
+        }
+    }
+    word.unwrap_or(Word { start: cursor, text: String::new(), quote: None, command })
+}
+
+/// Backslash-escapes the characters which would otherwise end a word or be
+/// expanded.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || is_operator_char(c) || "\"'\\$#*?[".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The longest text all of `candidates` start with.
+fn common_prefix(candidates: &[String]) -> &str {
+    let Some(first) = candidates.first() else { return "" };
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        let length = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.len()), |((index, _), _)| index);
+        prefix = &prefix[..length];
+    }
+    prefix
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// The programs in the directories of the shell's `$PATH`, and `names`,
+/// which start with `prefix`.
+fn commands(prefix: &str, names: &Names) -> BTreeSet<String> {
+    let mut commands: BTreeSet<String> = names.commands
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .cloned()
+        .collect();
+    for dir in env::split_paths(&names.path) {
+        let Ok(entries) = fs::read_dir(dir) else { continue };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                commands.insert(name);
+            }
+        }
+    }
+    commands
+}
+
+/**
+ *  The files whose paths start with `prefix`, as pairs of the path, with a
+ *  `/` after directories, and the file name. Hidden files are left out
+ *  unless the name being completed starts with a dot. Only directories
+ *  and programs are included when `executables` is set.
+ */
+fn paths(prefix: &str, executables: bool) -> Vec<(String, String)> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(index) => prefix.split_at(index + 1),
+        None => ("", prefix)
+    };
+    let lookup = if dir.is_empty() { PathBuf::from(".") } else { PathBuf::from(dir) };
+    let Ok(entries) = fs::read_dir(lookup) else { return vec![] };
+    let mut paths: Vec<(String, String)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !file_name.starts_with(name) || file_name.starts_with('.') && !name.starts_with('.') {
+                return None
+            }
+            let path = entry.path();
+            let file_name = if path.is_dir() {
+                file_name + "/"
+            } else if !executables || is_executable(&path) {
+                file_name
+            } else {
+                return None
+            };
+            Some((format!("{}{}", dir, file_name), file_name))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/**
+ *  Completes the word before `cursor` in `line`:
+ *
+ *  - a `$` followed by part of a name completes to a variable
+ *  - a word naming the command to run completes to a builtin, function or
+ *    program from `$PATH`, unless it has a `/` in it
+ *  - any other word completes to a file path, with the characters which
+ *    need it escaped, or in the quotes the word was started with
+ *
+ *  Returns `None` if nothing matches.
+ */
+pub fn complete(line: &str, cursor: usize, names: &Names) -> Option<Completion> {
+    let word = word_at(line, cursor);
+    let name_start = line[..cursor].trim_end_matches(is_name_char).len();
+    let before_name = &line[..name_start];
+    if word.quote != Some('\'') && before_name.ends_with('$') && !before_name.ends_with("\\$") {
+        let prefix = &line[name_start..cursor];
+        let variables: BTreeSet<&String> = names.variables.iter().filter(|name| name.starts_with(prefix)).collect();
+        let candidates: Vec<String> = variables.into_iter().cloned().collect();
+        let mut replacement = String::from(common_prefix(&candidates));
+        if candidates.len() == 1 && word.quote.is_none() {
+            replacement.push(' ');
+        }
+        return (!candidates.is_empty()).then_some(Completion { start: name_start, replacement, candidates })
+    }
+
+    let (values, candidates, finished): (Vec<String>, Vec<String>, bool) = if word.command && !word.text.contains('/') {
+        if word.text.is_empty() {
+            return None
+        }
+        let commands: Vec<String> = commands(&word.text, names).into_iter().collect();
+        (commands.clone(), commands, true)
+    } else {
+        let paths = paths(&word.text, word.command);
+        // A directory isn't finished, as a path can go on into it
+        let finished = paths.len() == 1 && !paths[0].0.ends_with('/');
+        let (values, candidates) = paths.into_iter().unzip();
+        (values, candidates, finished)
+    };
+    if values.is_empty() {
+        return None
+    }
+    let value = common_prefix(&values);
+    let finished = finished && values.len() == 1;
+    let replacement = match word.quote {
+        Some(quote) if finished => format!("{}{}{} ", quote, value, quote),
+        Some(quote) => format!("{}{}", quote, value),
+        None if finished => escape(value) + " ",
+        None => escape(value)
+    };
+    Some(Completion { start: word.start, replacement, candidates })
+}
+
+/**
+ *  Lays `items` out in columns to fit `width`, filling each column before
+ *  the next as `ls` does, and returns the rows.
+ */
+pub fn columns(items: &[String], width: usize) -> Vec<String> {
+    let column_width = items.iter().map(|item| item.width()).max().unwrap_or(0) + 2;
+    let column_count = (width / column_width).max(1);
+    let row_count = items.len().div_ceil(column_count);
+    (0..row_count)
+        .map(|row| {
+            let row: Vec<&String> = items.iter().skip(row).step_by(row_count).collect();
+            let mut text = String::new();
+            for (index, item) in row.iter().enumerate() {
+                text.push_str(item);
+                if index + 1 < row.len() {
+                    text.push_str(&" ".repeat(column_width - item.width()));
+                }
+            }
+            text
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(start: usize, text: &str, quote: Option<char>, command: bool) -> Word {
+        Word { start, text: String::from(text), quote, command }
+    }
+
+    #[test]
+    fn completion_test_1() {
+        assert_eq!(word_at("gi", 2), word(0, "gi", None, true));
+        assert_eq!(word_at("git st", 6), word(4, "st", None, false));
+        assert_eq!(word_at("ls | gr", 7), word(5, "gr", None, true));
+        assert_eq!(word_at("if tes", 6), word(3, "tes", None, true));
+        assert_eq!(word_at("cat > ", 6), word(6, "", None, false));
+        assert_eq!(word_at("cat My\\ Doc", 11), word(4, "My Doc", None, false));
+        assert_eq!(word_at("cat 'My Doc", 11), word(4, "My Doc", Some('\''), false));
+        assert_eq!(word_at("echo a; ", 8), word(8, "", None, true));
+
+        assert_eq!(escape("My Doc's (1).txt"), "My\\ Doc\\'s\\ \\(1\\).txt");
+        let candidates = [String::from("branch"), String::from("bra"), String::from("bisect")];
+        assert_eq!(common_prefix(&candidates[..2]), "bra");
+        assert_eq!(common_prefix(&candidates), "b");
+    }
+
+    #[test]
+    fn completion_test_2() {
+        let dir = env::temp_dir().join(format!("rush_completion_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("My Documents")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("notes.md"), "").unwrap();
+        let names = Names::default();
+        let complete = |line: &str| complete(line, line.len(), &names).map(|completion| {
+            (completion.start, completion.replacement, completion.candidates)
+        });
+        let d = dir.display();
+
+        let line = format!("cd {}/My", d);
+        assert_eq!(complete(&line), Some((3, format!("{}/My\\ Documents/", d), vec![String::from("My Documents/")])));
+        let line = format!("cat '{}/notes.t", d);
+        assert_eq!(complete(&line), Some((4, format!("'{}/notes.txt' ", d), vec![String::from("notes.txt")])));
+        let line = format!("cat {}/no", d);
+        let candidates = vec![String::from("notes.md"), String::from("notes.txt")];
+        assert_eq!(complete(&line), Some((4, format!("{}/notes.", d), candidates)));
+        let line = format!("cat {}/.h", d);
+        assert_eq!(complete(&line).unwrap().2, vec![String::from(".hidden/")]);
+        // Only directories and programs complete in command position
+        let line = format!("{}/no", d);
+        assert_eq!(complete(&line), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn completion_test_3() {
+        let names = Names {
+            commands: vec![String::from("history"), String::from("export")],
+            variables: vec![String::from("HOME"), String::from("HOSTNAME"), String::from("PATH")],
+            path: String::new()
+        };
+        let completion = complete("echo $HO", 8, &names).unwrap();
+        assert_eq!((completion.start, completion.replacement.as_str()), (6, "HO"));
+        assert_eq!(completion.candidates, vec![String::from("HOME"), String::from("HOSTNAME")]);
+        let completion = complete("echo \"$PA", 9, &names).unwrap();
+        assert_eq!(completion.replacement, "PATH");
+        assert_eq!(complete("echo '$PA", 9, &names).map(|completion| completion.start), None);
+        let completion = complete("histo", 5, &names).unwrap();
+        assert_eq!(completion.replacement, "history ");
+        assert_eq!(complete("", 0, &names), None);
+
+        let items: Vec<String> = ["a", "bb", "c", "dddd", "e"].iter().map(|item| String::from(*item)).collect();
+        assert_eq!(columns(&items, 14), vec!["a     dddd", "bb    e", "c"]);
+        assert_eq!(columns(&items, 4), vec!["a", "bb", "c", "dddd", "e"]);
+    }
+
+    #[test]
+    fn completion_test_4() {
+        let dir = env::temp_dir().join(format!("rush_path_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("rush-prog"), "").unwrap();
+        fs::set_permissions(dir.join("rush-prog"), fs::Permissions::from_mode(0o755)).unwrap();
+        // Programs are looked for in the shell's $PATH, not the process's
+        let mut names = Names::default();
+        assert_eq!(complete("rush-pr", 7, &names), None);
+        names.path = dir.display().to_string();
+        assert_eq!(complete("rush-pr", 7, &names).unwrap().replacement, "rush-prog ");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,362 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// A line entered at the prompt, and how running it went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub line: String,
+    /// When the line was entered, in seconds since the Unix epoch.
+    pub time: u64,
+    /// The working directory the line was entered in.
+    pub cwd: String,
+    /// How long the line took to run, in milliseconds.
+    pub duration: u64,
+    pub status: i32
+}
+
+impl Entry {
+    /// Entries are stored one per line, as the time, duration, status,
+    /// directory and line separated by tabs.
+    fn to_record(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}", self.time, self.duration, self.status, escape(&self.cwd), escape(&self.line))
+    }
+
+    /// Reads an entry back from its record. Lines written before entries
+    /// had metadata are read as entries without it.
+    fn from_record(record: &str) -> Self {
+        let fields: Vec<&str> = record.splitn(5, '\t').collect();
+        if let [time, duration, status, cwd, line] = fields[..] {
+            if let (Ok(time), Ok(duration), Ok(status)) = (time.parse(), duration.parse(), status.parse()) {
+                return Entry { line: unescape(line), time, cwd: unescape(cwd), duration, status }
+            }
+        }
+        Entry {
+            line: unescape(record),
+            ..Entry::default()
+        }
+    }
+}
+
+/// The lines entered at the prompt, oldest first, and the file they're kept
+/// in between sessions.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<Entry>,
+    path: Option<PathBuf>
+}
+
+/// The history file in the home directory, if there is one.
+pub fn default_path() -> Option<PathBuf> {
+    home::home_dir().map(|home| home.join(".rush_history"))
+}
+
+/// Records are one per line with tab-separated fields, so newlines and tabs
+/// in fields, and the backslashes used to escape those, are escaped.
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            },
+            ('\\', Some('t')) => {
+                entry.push('\t');
+                chars.next();
+            },
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            },
+            _ => entry.push(c)
+        }
+    }
+    entry
+}
+
+impl History {
+    /// Loads the history kept in `path`, which is created when the first
+    /// entry is added if it doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(Entry::from_record).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e)
+        };
+        Ok(History {
+            entries,
+            path: Some(PathBuf::from(path))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The line of the entry at `index`.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.line.as_str())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /**
+     *  Adds an entry, and appends it to the history file. Blank entries and
+     *  repeats of the newest entry are left out. Each entry is written with
+     *  a single append while holding a lock on the file, so the entries of
+     *  shells sharing the file never interleave.
+     */
+    pub fn add(&mut self, entry: Entry) -> io::Result<()> {
+        if entry.line.trim().is_empty() || self.entries.last().is_some_and(|last| last.line == entry.line) {
+            return Ok(())
+        }
+        let record = format!("{}\n", entry.to_record());
+        self.entries.push(entry);
+
+        let Some(path) = &self.path else { return Ok(()) };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        lock(&file)?;
+        file.write_all(record.as_bytes())
+    }
+
+    /**
+     *  Removes the entry at `index`, and its record from the history file.
+     *  The file is rewritten in place while holding the lock, keeping the
+     *  entries other shells have added since this one loaded it.
+     */
+    pub fn remove(&mut self, index: usize) -> io::Result<()> {
+        let entry = self.entries.remove(index);
+        let Some(path) = &self.path else { return Ok(()) };
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        lock(&file)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let mut records: Vec<&str> = text.lines().collect();
+        if let Some(position) = records.iter().position(|record| Entry::from_record(record) == entry) {
+            records.remove(position);
+        }
+        let text: String = records.iter().map(|record| format!("{}\n", record)).collect();
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(text.as_bytes())
+    }
+
+    /// The newest entry before `index` which starts with `prefix` and isn't
+    /// `current`.
+    pub fn search_back(&self, index: usize, prefix: &str, current: &str) -> Option<usize> {
+        self.entries[..index.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.line.starts_with(prefix) && entry.line != current)
+    }
+
+    /// The oldest entry after `index` which starts with `prefix` and isn't
+    /// `current`.
+    pub fn search_forward(&self, index: usize, prefix: &str, current: &str) -> Option<usize> {
+        let start = (index + 1).min(self.entries.len());
+        self.entries[start..]
+            .iter()
+            .position(|entry| entry.line.starts_with(prefix) && entry.line != current)
+            .map(|offset| start + offset)
+    }
+
+    /// The newest entry before `index` which contains `text` and isn't
+    /// `current`, and where in it the last occurrence of `text` starts.
+    pub fn search_text(&self, index: usize, text: &str, current: &str) -> Option<(usize, usize)> {
+        self.entries[..index.min(self.entries.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| entry.line != current)
+            .find_map(|(index, entry)| entry.line.rfind(text).map(|offset| (index, offset)))
+    }
+
+    /**
+     *  Expands csh-style history references in `line`:
+     *
+     *  - `!!` is the last line, and `!$` the last word of it
+     *  - `!n` is line `n`, counting from 1, and `!-n` the `n`th line back
+     *  - `!prefix` is the newest line starting with `prefix`
+     *
+     *  A `!` in single quotes, after a backslash, or followed by whitespace,
+     *  `=` or `(` is left alone. Returns the reference which matched no
+     *  line as the error.
+     */
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let mut expanded = String::new();
+        let mut in_quotes = false;
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let after = &rest[c.len_utf8()..];
+            match c {
+                '\'' => in_quotes = !in_quotes,
+                '\\' if !in_quotes => {
+                    if let Some(next) = after.chars().next() {
+                        expanded.push(c);
+                        expanded.push(next);
+                        rest = &after[next.len_utf8()..];
+                        continue
+                    }
+                },
+                '!' if !in_quotes => {
+                    let length = reference_length(after);
+                    if length > 0 {
+                        let reference = &after[..length];
+                        match self.reference(reference) {
+                            Some(text) => expanded.push_str(&text),
+                            None => return Err(format!("!{}", reference))
+                        }
+                        rest = &after[length..];
+                        continue
+                    }
+                },
+                _ => {}
+            }
+            expanded.push(c);
+            rest = after;
+        }
+        Ok(expanded)
+    }
+
+    /// The text a history reference, without its `!`, stands for.
+    fn reference(&self, reference: &str) -> Option<String> {
+        let last = self.entries.last().map(|entry| entry.line.as_str());
+        let index = match reference {
+            "!" => return last.map(String::from),
+            "$" => return last.and_then(|line| line.split_whitespace().last()).map(String::from),
+            _ => match reference.parse::<i64>() {
+                Ok(n) if n < 0 => self.len().checked_sub(n.unsigned_abs() as usize),
+                Ok(n) => (n as usize).checked_sub(1),
+                Err(_) => self.search_back(self.len(), reference, "")
+            }
+        };
+        index.and_then(|index| self.get(index)).map(String::from)
+    }
+}
+
+/// The length of the history reference at the start of `text`, which
+/// follows a `!`, or 0 if there isn't one.
+fn reference_length(text: &str) -> usize {
+    match text.chars().next() {
+        Some('!' | '$') => 1,
+        Some(c) if c.is_whitespace() || c == '=' || c == '(' => 0,
+        Some('-') if text[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+            1 + text[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len() - 1)
+        },
+        Some(c) if c.is_ascii_digit() => text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()),
+        Some(_) => text.find(|c: char| c.is_whitespace() || ";|&<>()'\"".contains(c)).unwrap_or(text.len()),
+        None => 0
+    }
+}
+
+/// Waits for an exclusive lock on `file`, which is released when the file
+/// is closed.
+fn lock(file: &fs::File) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: &str) -> Entry {
+        Entry {
+            line: String::from(line),
+            ..Entry::default()
+        }
+    }
+
+    #[test]
+    fn history_test_1() {
+        let mut history = History::default();
+        for entry in ["git status", "ls", "git commit", "git commit", "  ", "git push"] {
+            history.add(self::entry(entry)).unwrap();
+        }
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.search_back(4, "git", ""), Some(3));
+        assert_eq!(history.search_back(3, "git", "git push"), Some(2));
+        assert_eq!(history.search_back(2, "git", "git commit"), Some(0));
+        assert_eq!(history.search_back(0, "git", ""), None);
+        assert_eq!(history.search_forward(0, "git c", ""), Some(2));
+        assert_eq!(history.search_forward(2, "git c", ""), None);
+        assert_eq!(history.search_back(4, "", "git push"), Some(2));
+
+        assert_eq!(history.search_text(4, "it", ""), Some((3, 1)));
+        assert_eq!(history.search_text(4, "it", "git push"), Some((2, 8)));
+        assert_eq!(history.search_text(2, "commit", ""), None);
+        assert_eq!(history.search_text(4, "s", "git push"), Some((1, 1)));
+    }
+
+    #[test]
+    fn history_test_2() {
+        let path = std::env::temp_dir().join(format!("rush_history_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let writers: Vec<_> = (0..4).map(|writer| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let mut history = History::load(&path).unwrap();
+                for line in 0..50 {
+                    history.add(entry(&format!("echo {} {}", writer, line))).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let mut history = History::load(&path).unwrap();
+        assert_eq!(history.len(), 200);
+        assert!((0..200).all(|index| history.get(index).unwrap().starts_with("echo ")));
+
+        let heredoc = Entry {
+            line: String::from("cat <<EOF\na\\b\tc\nEOF"),
+            time: 1760000000,
+            cwd: String::from("/tmp/a\tb"),
+            duration: 1500,
+            status: 1
+        };
+        history.add(heredoc.clone()).unwrap();
+        history.add(entry("echo last")).unwrap();
+        let mut history = History::load(&path).unwrap();
+        assert_eq!(history.entries()[200], heredoc);
+
+        // Removing an entry keeps the ones other shells added since
+        History::load(&path).unwrap().add(entry("echo other")).unwrap();
+        history.remove(200).unwrap();
+        let history = History::load(&path).unwrap();
+        assert_eq!(history.len(), 202);
+        assert_eq!(history.get(200), Some("echo last"));
+        assert_eq!(history.get(201), Some("echo other"));
+
+        // Lines from before entries had metadata can be removed too
+        fs::write(&path, "echo old\n").unwrap();
+        let mut history = History::load(&path).unwrap();
+        history.remove(0).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn history_test_3() {
+        let mut history = History::default();
+        for line in ["make test", "cd src", "git add parser.rs lexer.rs", "git diff"] {
+            history.add(entry(line)).unwrap();
+        }
+        assert_eq!(history.expand("sudo !!"), Ok(String::from("sudo git diff")));
+        assert_eq!(history.expand("vim !$;!1"), Ok(String::from("vim diff;make test")));
+        assert_eq!(history.expand("!-2 && !cd"), Ok(String::from("git add parser.rs lexer.rs && cd src")));
+        assert_eq!(history.expand("echo '!!' \\!! ! a!=b !("), Ok(String::from("echo '!!' \\!! ! a!=b !(")));
+        assert_eq!(history.expand("!ls"), Err(String::from("!ls")));
+        assert_eq!(history.expand("!9"), Err(String::from("!9")));
+    }
+}
@@ -0,0 +1,418 @@
+use std::io::{self, Write};
+
+use crossterm::cursor::{MoveTo, MoveToColumn, MoveUp};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::queue;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+mod buffer;
+pub mod completion;
+pub mod history;
+use buffer::LineBuffer;
+use completion::Names;
+use history::History;
+
+/// What a key press did to the line being edited.
+enum Outcome {
+    Editing,
+    Done(String),
+    Interrupted,
+    EndOfInput
+}
+
+/// What reading a line ended with.
+#[derive(Debug, PartialEq)]
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C discarded the line.
+    Interrupted,
+    EndOfInput
+}
+
+/// The state of a Ctrl-R search through the history.
+struct Search {
+    query: String,
+    /// The history entry last matched, or where the search started before
+    /// anything has matched.
+    index: usize,
+    /// Whether nothing older matches the query.
+    failed: bool,
+    /// The line and history entry shown before the search, which Ctrl-G
+    /// goes back to.
+    line: String,
+    line_index: usize
+}
+
+impl Search {
+    fn prompt(&self) -> String {
+        let failed = if self.failed { "failed " } else { "" };
+        format!("({}reverse-i-search)`{}': ", failed, self.query)
+    }
+}
+
+/**
+ *  Reads lines from the terminal in raw mode, with emacs-style editing:
+ *
+ *  - Ctrl-B and Ctrl-F or the arrow keys move by character, and Alt-B and
+ *    Alt-F by word
+ *  - Ctrl-A or Home and Ctrl-E or End move to the start and end of the line
+ *  - Ctrl-U, Ctrl-K, Ctrl-W and Alt-D kill text, which Ctrl-Y yanks back
+ *  - Up and Down recall older and newer lines from the history which
+ *    start with the text typed before the first Up
+ *  - Ctrl-R searches back through the history for lines containing the
+ *    text typed after it, and again for older ones; Enter runs the line
+ *    found, Ctrl-G or Escape go back to the line before the search, and
+ *    any other key leaves the line found for editing
+ *  - Tab completes the word before the cursor, listing the candidates
+ *    when it can't be completed any further
+ *  - Ctrl-L clears the screen, Ctrl-C discards the line and Ctrl-D on an
+ *    empty line ends the input
+ *
+ *  Lines longer than the terminal is wide wrap onto the rows below, and are
+ *  laid out again when the terminal is resized.
+ */
+#[derive(Default)]
+pub struct Editor {
+    buffer: LineBuffer,
+    /// The last line of the prompt, which is redrawn with the text.
+    prompt: String,
+    /// The row the cursor was last drawn on, counted from the prompt's row.
+    cursor_row: usize,
+    width: usize,
+    /// The history entry being shown, or the history's length for the
+    /// line being typed.
+    history_index: usize,
+    /// The line being typed before moving into the history, which is both
+    /// the prefix entries are filtered by and the line moved back to.
+    draft: String,
+    search: Option<Search>,
+    /// The query of the last search, which Ctrl-R with no query repeats.
+    last_query: String,
+    /// The builtins, functions and variables which Tab completes.
+    names: Names
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_names(&mut self, names: Names) {
+        self.names = names;
+    }
+
+    /// Prompts for a line and reads it, without its newline, recalling
+    /// lines from `history`.
+    pub fn read_line(&mut self, prompt: &str, history: &History) -> io::Result<ReadLine> {
+        let mut out = io::stdout();
+        let (lines, last) = match prompt.rsplit_once('\n') {
+            Some((lines, last)) => (Some(lines), last),
+            None => (None, prompt)
+        };
+        if let Some(lines) = lines {
+            writeln!(out, "{}", lines)?;
+        }
+        self.prompt = String::from(last);
+        self.buffer.set("");
+        self.cursor_row = 0;
+        self.history_index = history.len();
+        self.draft.clear();
+        self.search = None;
+        self.width = (terminal::size()?.0 as usize).max(1);
+
+        terminal::enable_raw_mode()?;
+        let result = self.edit(history);
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn edit(&mut self, history: &History) -> io::Result<ReadLine> {
+        self.redraw()?;
+        loop {
+            let outcome = match event::read()? {
+                Event::Key(key) => self.handle_key(key, history)?,
+                Event::Resize(width, _) => {
+                    self.width = (width as usize).max(1);
+                    Outcome::Editing
+                },
+                _ => continue
+            };
+            match outcome {
+                Outcome::Editing => self.redraw()?,
+                Outcome::Done(line) => return Ok(ReadLine::Line(line)),
+                Outcome::Interrupted => return Ok(ReadLine::Interrupted),
+                Outcome::EndOfInput => return Ok(ReadLine::EndOfInput)
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, history: &History) -> io::Result<Outcome> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        if self.search.is_some() && self.handle_search_key(key.code, control, alt, history) {
+            return Ok(Outcome::Editing)
+        }
+        let buffer = &mut self.buffer;
+        match key.code {
+            KeyCode::Enter => return self.finish("").map(|_| Outcome::Done(String::from(self.buffer.text()))),
+            KeyCode::Char('c') if control => return self.finish("^C").map(|_| Outcome::Interrupted),
+            KeyCode::Char('d') if control && buffer.text().is_empty() => {
+                self.finish("")?;
+                return Ok(Outcome::EndOfInput)
+            },
+            KeyCode::Char('d') if control => { buffer.delete_forward(); },
+            KeyCode::Tab => self.complete()?,
+            KeyCode::Char('r') if control => self.start_search(),
+            KeyCode::Char('l') if control => {
+                queue!(io::stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
+                self.cursor_row = 0;
+            },
+            KeyCode::Char('a') if control => buffer.move_start(),
+            KeyCode::Char('e') if control => buffer.move_end(),
+            KeyCode::Char('b') if control => buffer.move_left(),
+            KeyCode::Char('f') if control => buffer.move_right(),
+            KeyCode::Char('h') if control => { buffer.delete_back(); },
+            KeyCode::Char('u') if control => buffer.kill_to_start(),
+            KeyCode::Char('k') if control => buffer.kill_to_end(),
+            KeyCode::Char('w') if control => buffer.kill_word_back(),
+            KeyCode::Char('y') if control => buffer.yank(),
+            KeyCode::Char('b') if alt => buffer.move_word_left(),
+            KeyCode::Char('f') if alt => buffer.move_word_right(),
+            KeyCode::Char('d') if alt => buffer.kill_word_forward(),
+            KeyCode::Char(c) if !control && !alt => buffer.insert(c.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace => { buffer.delete_back(); },
+            KeyCode::Delete => { buffer.delete_forward(); },
+            KeyCode::Up => self.recall_older(history),
+            KeyCode::Down => self.recall_newer(history),
+            KeyCode::Left => buffer.move_left(),
+            KeyCode::Right => buffer.move_right(),
+            KeyCode::Home => buffer.move_start(),
+            KeyCode::End => buffer.move_end(),
+            _ => {}
+        }
+        Ok(Outcome::Editing)
+    }
+
+    /// Up: replaces the line with the next older history entry starting
+    /// with the draft.
+    fn recall_older(&mut self, history: &History) {
+        if self.history_index == history.len() {
+            self.draft = String::from(self.buffer.text());
+        }
+        if let Some(index) = history.search_back(self.history_index, &self.draft, self.buffer.text()) {
+            self.history_index = index;
+            self.buffer.set(history.get(index).unwrap());
+        }
+    }
+
+    /// Down: replaces the line with the next newer history entry starting
+    /// with the draft, or with the draft itself after the newest.
+    fn recall_newer(&mut self, history: &History) {
+        if self.history_index == history.len() {
+            return
+        }
+        match history.search_forward(self.history_index, &self.draft, self.buffer.text()) {
+            Some(index) => {
+                self.history_index = index;
+                self.buffer.set(history.get(index).unwrap());
+            },
+            None => {
+                self.history_index = history.len();
+                self.buffer.set(&self.draft);
+            }
+        }
+    }
+
+    /// Tab: completes the word before the cursor as far as all of the
+    /// candidates agree, or lists them below the line if it's as far as
+    /// they agree already.
+    fn complete(&mut self) -> io::Result<()> {
+        let cursor = self.buffer.cursor();
+        let Some(completion) = completion::complete(self.buffer.text(), cursor, &self.names) else { return Ok(()) };
+        if completion.replacement != self.buffer.text()[completion.start..cursor] {
+            self.buffer.replace(completion.start..cursor, &completion.replacement);
+        } else if completion.candidates.len() > 1 {
+            self.finish("")?;
+            let mut out = io::stdout();
+            for row in completion::columns(&completion.candidates, self.width) {
+                queue!(out, Print(row), Print("\r\n"))?;
+            }
+            self.cursor_row = 0;
+            self.buffer.move_to(cursor);
+        }
+        Ok(())
+    }
+
+    fn start_search(&mut self) {
+        self.search = Some(Search {
+            query: String::new(),
+            index: self.history_index,
+            failed: false,
+            line: String::from(self.buffer.text()),
+            line_index: self.history_index
+        });
+    }
+
+    /**
+     *  Handles a key while searching, returning false if it ended the search
+     *  and is to be handled as usual. Typing adds to the query and looks for
+     *  it from the entry matched so far, backspace looks for the shorter
+     *  query from where the search started, and Ctrl-R looks further back.
+     */
+    fn handle_search_key(&mut self, code: KeyCode, control: bool, alt: bool, history: &History) -> bool {
+        let search = self.search.as_mut().unwrap();
+        match code {
+            KeyCode::Char('r') if control && search.query.is_empty() => {
+                search.query = self.last_query.clone();
+                let index = search.index + 1;
+                self.find(index, false, history);
+            },
+            KeyCode::Char('r') if control => {
+                let index = search.index;
+                self.find(index, true, history);
+            },
+            KeyCode::Char('g') if control => self.cancel_search(),
+            KeyCode::Esc => self.cancel_search(),
+            KeyCode::Char(c) if !control && !alt => {
+                search.query.push(c);
+                let index = search.index + 1;
+                self.find(index, false, history);
+            },
+            KeyCode::Backspace => {
+                search.query.pop();
+                let index = search.line_index;
+                self.buffer.set(&search.line);
+                self.find(index, false, history);
+            },
+            _ => {
+                let search = self.search.take().unwrap();
+                if !search.query.is_empty() {
+                    self.last_query = search.query;
+                }
+                self.draft.clear();
+                return false
+            }
+        }
+        true
+    }
+
+    fn cancel_search(&mut self) {
+        let search = self.search.take().unwrap();
+        self.buffer.set(&search.line);
+        self.history_index = search.line_index;
+    }
+
+    /// Shows the newest entry before `index` which contains the query, with
+    /// the cursor where the query starts in it. Looking `again` skips
+    /// entries the same as the line shown.
+    fn find(&mut self, index: usize, again: bool, history: &History) {
+        let search = self.search.as_mut().unwrap();
+        if search.query.is_empty() {
+            search.failed = false;
+            return
+        }
+        let current = if again { self.buffer.text() } else { "" };
+        match history.search_text(index, &search.query, current) {
+            Some((index, offset)) => {
+                search.index = index;
+                search.failed = false;
+                self.history_index = index;
+                self.buffer.set(history.get(index).unwrap());
+                self.buffer.move_to(offset);
+            },
+            None => search.failed = true
+        }
+    }
+
+    /// Redraws the prompt and text with the cursor after them, then writes
+    /// `marker` and moves to the next line, leaving the line as entered.
+    fn finish(&mut self, marker: &str) -> io::Result<()> {
+        self.buffer.move_end();
+        self.redraw()?;
+        let mut out = io::stdout();
+        queue!(out, Print(marker), Print("\r\n"))?;
+        out.flush()
+    }
+
+    /// Draws the prompt and text over what was drawn before, and puts the
+    /// cursor in place.
+    fn redraw(&mut self) -> io::Result<()> {
+        let mut out = io::stdout();
+        if self.cursor_row > 0 {
+            queue!(out, MoveUp(self.cursor_row as u16))?;
+        }
+        queue!(out, Print("\r"), Clear(ClearType::FromCursorDown))?;
+
+        let text = self.buffer.text();
+        let prompt = match &self.search {
+            Some(search) => search.prompt(),
+            None => self.prompt.clone()
+        };
+        queue!(out, Print(&prompt))?;
+        match &self.search {
+            // The match starts at the cursor, and is highlighted
+            Some(search) if !search.failed && !search.query.is_empty() => {
+                let (start, end) = (self.buffer.cursor(), self.buffer.cursor() + search.query.len());
+                queue!(out, Print(&text[..start]), SetAttribute(Attribute::Reverse), Print(&text[start..end]))?;
+                queue!(out, SetAttribute(Attribute::Reset), Print(&text[end..]))?;
+            },
+            _ => queue!(out, Print(text))?
+        }
+
+        let prompt_end = position((0, 0), &prompt, self.width);
+        let (end_row, end_column) = position(prompt_end, text, self.width);
+        let (row, column) = position(prompt_end, &text[..self.buffer.cursor()], self.width);
+        // After filling the last column the terminal holds the cursor there,
+        // so move it onto the next row as the position expects
+        if end_column == 0 && end_row > 0 {
+            queue!(out, Print("\r\n"))?;
+        }
+        if end_row > row {
+            queue!(out, MoveUp((end_row - row) as u16))?;
+        }
+        // Columns are 0-based here, and 1-based in crossterm 0.23
+        queue!(out, MoveToColumn(column as u16 + 1))?;
+        self.cursor_row = row;
+        out.flush()
+    }
+}
+
+/// Where the cursor ends up after writing `text` from `start`, a row and
+/// column, on a terminal `width` columns wide. Like a terminal, a wide
+/// character which doesn't fit at the end of a row goes on the next one.
+fn position(start: (usize, usize), text: &str, width: usize) -> (usize, usize) {
+    let (mut row, mut column) = start;
+    for grapheme in text.graphemes(true) {
+        let grapheme_width = grapheme.width();
+        if column + grapheme_width > width {
+            row += 1;
+            column = 0;
+        }
+        column += grapheme_width;
+        if column >= width {
+            row += 1;
+            column = 0;
+        }
+    }
+    (row, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_test_1() {
+        assert_eq!(position((0, 0), "rush > ", 80), (0, 7));
+        assert_eq!(position((0, 7), "e\u{301}", 80), (0, 8));
+        // Filling the last column moves on to the next row
+        assert_eq!(position((0, 0), "abcd", 4), (1, 0));
+        assert_eq!(position((0, 0), "abcde", 4), (1, 1));
+        // A wide character doesn't split across rows
+        assert_eq!(position((0, 0), "abc漢", 4), (1, 2));
+        assert_eq!(position((0, 2), "漢字漢", 4), (2, 0));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::env;
+
+use super::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScopeKind {
+    Global,
+    Function,
+    Block
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub value: Value,
+    /// Exported variables are passed to child processes in their environment.
+    pub exported: bool
+}
+
+struct Scope {
+    kind: ScopeKind,
+    variables: HashMap<String, Variable>
+}
+
+/**
+ *  The shell's variable store.
+ *
+ *  Scopes are nested global, function and block scopes. Lookups search
+ *  outwards from the innermost scope, but skip the scopes of calling
+ *  functions, so a function only sees its own variables and globals.
+ */
+pub struct Environment {
+    scopes: Vec<Scope>
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Environment {
+    /// Creates an environment whose global scope holds the process's
+    /// environment variables, all marked as exported. Those which aren't
+    /// valid UTF-8 are left out, rather than passed on mangled.
+    pub fn new() -> Self {
+        let variables = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .map(|(name, value)| (name, Variable {
+                value: Value::String(value),
+                exported: true
+            }))
+            .collect();
+        Environment {
+            scopes: vec![Scope {
+                kind: ScopeKind::Global,
+                variables
+            }]
+        }
+    }
+
+    pub fn push_scope(&mut self, kind: ScopeKind) {
+        self.scopes.push(Scope {
+            kind,
+            variables: HashMap::new()
+        });
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Whether a function is currently being run.
+    pub fn in_function(&self) -> bool {
+        self.scopes.iter().any(|scope| scope.kind == ScopeKind::Function)
+    }
+
+    /// The scopes visible from the innermost one, innermost first.
+    fn visible(&self) -> impl Iterator<Item = usize> + '_ {
+        let function_start = self.scopes
+            .iter()
+            .rposition(|scope| scope.kind == ScopeKind::Function)
+            .unwrap_or(0);
+        (function_start..self.scopes.len())
+            .rev()
+            .chain(std::iter::once(0).filter(move |_| function_start != 0))
+    }
+
+    fn find(&self, name: &str) -> Option<&Variable> {
+        self.visible().find_map(|index| self.scopes[index].variables.get(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.find(name).map(|variable| variable.value.clone())
+    }
+
+    /// Defines a shell-local variable in the innermost scope, as `let` does.
+    pub fn define(&mut self, name: &str, value: Value) {
+        let scope = self.scopes.last_mut().unwrap();
+        let exported = scope.variables.get(name).is_some_and(|variable| variable.exported);
+        scope.variables.insert(String::from(name), Variable {
+            value,
+            exported
+        });
+    }
+
+    /// Assigns to the visible variable called `name`, or defines a new
+    /// global if there isn't one.
+    pub fn set(&mut self, name: &str, value: Value) {
+        let index = self.visible()
+            .find(|index| self.scopes[*index].variables.contains_key(name))
+            .unwrap_or(0);
+        let exported = self.scopes[index].variables.get(name).is_some_and(|variable| variable.exported);
+        self.scopes[index].variables.insert(String::from(name), Variable {
+            value,
+            exported
+        });
+    }
+
+    /// Marks the visible variable called `name` as exported, defining it as
+    /// an empty global if it doesn't exist yet.
+    pub fn export(&mut self, name: &str) {
+        if self.find(name).is_none() {
+            self.set(name, Value::String(String::new()));
+        }
+        let index = self.visible()
+            .find(|index| self.scopes[*index].variables.contains_key(name))
+            .unwrap();
+        if let Some(variable) = self.scopes[index].variables.get_mut(name) {
+            variable.exported = true;
+        }
+    }
+
+    /// The names of every visible variable.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.visible()
+            .flat_map(|index| self.scopes[index].variables.keys().cloned())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The names and values of every visible exported variable, for the
+    /// environment of a child process.
+    pub fn exported(&self) -> HashMap<String, String> {
+        let mut exported = HashMap::new();
+        for index in self.visible() {
+            for (name, variable) in &self.scopes[index].variables {
+                if !exported.contains_key(name) {
+                    exported.insert(name.clone(), variable.exported.then(|| variable.value.to_string()));
+                }
+            }
+        }
+        exported
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name, value)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_test_1() {
+        let mut e = Environment::new();
+        e.define("x", Value::Number(1.0));
+        e.push_scope(ScopeKind::Block);
+        e.define("x", Value::Number(2.0));
+        e.define("y", Value::Number(3.0));
+        assert_eq!(e.get("x"), Some(Value::Number(2.0)));
+        e.pop_scope();
+        assert_eq!(e.get("x"), Some(Value::Number(1.0)));
+        assert_eq!(e.get("y"), None);
+    }
+
+    #[test]
+    fn environment_test_2() {
+        let mut e = Environment::new();
+        e.define("global", Value::Number(1.0));
+        e.push_scope(ScopeKind::Function);
+        e.define("caller", Value::Number(2.0));
+        e.push_scope(ScopeKind::Function);
+        assert_eq!(e.get("global"), Some(Value::Number(1.0)));
+        assert_eq!(e.get("caller"), None);
+        e.set("global", Value::Number(4.0));
+        e.pop_scope();
+        e.pop_scope();
+        assert_eq!(e.get("global"), Some(Value::Number(4.0)));
+    }
+
+    #[test]
+    fn environment_test_3() {
+        let mut e = Environment::new();
+        e.define("RUSH_LOCAL", Value::String(String::from("a")));
+        assert!(!e.exported().contains_key("RUSH_LOCAL"));
+        e.export("RUSH_LOCAL");
+        e.define("RUSH_LOCAL", Value::String(String::from("b")));
+        assert_eq!(e.exported().get("RUSH_LOCAL"), Some(&String::from("b")));
+        e.push_scope(ScopeKind::Block);
+        e.define("RUSH_LOCAL", Value::String(String::from("c")));
+        assert!(!e.exported().contains_key("RUSH_LOCAL"));
+    }
+}
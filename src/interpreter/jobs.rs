@@ -0,0 +1,282 @@
+use std::fmt;
+
+use super::process::{self, pid_t, WaitStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Every process has exited, and this is the job's status: that of the
+    /// last process, or with `pipefail` that of the last one which failed.
+    Done(i32)
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(0) => write!(f, "Done"),
+            JobState::Done(status) => write!(f, "Exit {}", status)
+        }
+    }
+}
+
+/// A pipeline started by the shell, whose processes share a process group
+/// when job control is enabled.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,
+    /// The process group, which is the pid of the first process.
+    pub pgid: pid_t,
+    pub pids: Vec<pid_t>,
+    /// The exit status of each process, once it has exited.
+    statuses: Vec<Option<i32>>,
+    /// The pipeline's commands, for listings and notices.
+    pub command: String,
+    pub state: JobState,
+    /// Whether any failed process fails the job, rather than only the last.
+    pub pipefail: bool
+}
+
+impl Job {
+    /// A running job which hasn't been given a number yet.
+    pub fn new(pids: Vec<pid_t>, command: String) -> Self {
+        Job {
+            id: 0,
+            pgid: pids.first().copied().unwrap_or(0),
+            statuses: vec![None; pids.len()],
+            pids,
+            command,
+            state: JobState::Running,
+            pipefail: false
+        }
+    }
+
+    /// The exit status of each process, with 0 for any still running.
+    pub fn statuses(&self) -> Vec<i32> {
+        self.statuses.iter().map(|status| status.unwrap_or(0)).collect()
+    }
+
+    /// Collects the status of any of the job's processes which have changed
+    /// state, without blocking, and updates the job's state.
+    fn poll(&mut self) {
+        for (pid, status) in self.pids.iter().zip(self.statuses.iter_mut()) {
+            if status.is_some() {
+                continue;
+            }
+            match process::try_wait_pid(*pid) {
+                Ok(Some(WaitStatus::Exited(exited))) => *status = Some(exited),
+                Ok(Some(WaitStatus::Stopped)) => self.state = JobState::Stopped,
+                Ok(Some(WaitStatus::Continued)) => self.state = JobState::Running,
+                Ok(None) => {},
+                // A process that can't be waited on has already been reaped
+                Err(_) => *status = Some(1)
+            }
+        }
+        self.update_done();
+    }
+
+    /// Blocks until every process of the job has exited or the job is
+    /// stopped, as a foreground job is by Ctrl-Z, and returns its state.
+    pub fn wait_or_stop(&mut self) -> JobState {
+        for (pid, status) in self.pids.iter().zip(self.statuses.iter_mut()) {
+            if status.is_some() {
+                continue;
+            }
+            match process::wait_pid_or_stop(*pid) {
+                Ok(WaitStatus::Stopped) => {
+                    self.state = JobState::Stopped;
+                    return self.state
+                },
+                Ok(WaitStatus::Exited(exited)) => *status = Some(exited),
+                Ok(WaitStatus::Continued) => unreachable!("continues aren't waited for"),
+                Err(_) => *status = Some(1)
+            }
+        }
+        self.update_done();
+        self.state
+    }
+
+    /// Blocks until every process of the job has exited, returning the exit
+    /// status of the last one.
+    pub fn wait(&mut self) -> i32 {
+        for (pid, status) in self.pids.iter().zip(self.statuses.iter_mut()) {
+            if status.is_none() {
+                *status = Some(process::wait_pid(*pid).unwrap_or(1));
+            }
+        }
+        self.update_done();
+        match self.state {
+            JobState::Done(status) => status,
+            _ => unreachable!("every process has exited")
+        }
+    }
+
+    /// Sends SIGCONT to each of the job's processes, so a stopped job carries on.
+    pub fn resume(&mut self) {
+        for (pid, status) in self.pids.iter().zip(&self.statuses) {
+            if status.is_none() {
+                let _ = process::kill(*pid, libc::SIGCONT);
+            }
+        }
+        self.state = JobState::Running;
+    }
+
+    fn update_done(&mut self) {
+        if let [.., Some(last)] = self.statuses[..] {
+            if self.statuses.iter().all(Option::is_some) {
+                let failed = self.statuses.iter().rev().flatten().find(|status| **status != 0);
+                self.state = JobState::Done(match failed {
+                    Some(failed) if self.pipefail => *failed,
+                    _ => last
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}]  {:<24}{}", self.id, self.state.to_string(), self.command)
+    }
+}
+
+/// The jobs the shell is tracking: those started in the background, and
+/// those stopped while in the foreground.
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>
+}
+
+impl JobTable {
+    /// Adds a job, numbering it one higher than the newest job unless it
+    /// has a number from being in the table before.
+    pub fn add(&mut self, mut job: Job) -> &Job {
+        if job.id == 0 {
+            job.id = self.jobs.last().map_or(1, |job| job.id + 1);
+        }
+        let index = self.jobs.partition_point(|other| other.id < job.id);
+        self.jobs.insert(index, job);
+        &self.jobs[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    /// Updates the state of every job without blocking.
+    pub fn poll(&mut self) {
+        for job in &mut self.jobs {
+            job.poll();
+        }
+    }
+
+    /// Removes and returns the jobs which have finished since the last call.
+    pub fn reap(&mut self) -> Vec<Job> {
+        self.poll();
+        let (finished, running) = self.jobs
+            .drain(..)
+            .partition(|job| matches!(job.state, JobState::Done(_)));
+        self.jobs = running;
+        finished
+    }
+
+    /**
+     *  Removes and returns the job referred to by `spec`: `%n` for job n,
+     *  `%%`, `%+` or no spec for the newest job, `%-` for the one before it,
+     *  and `%text` for the newest job whose command starts with the text.
+     */
+    pub fn take(&mut self, spec: Option<&str>) -> Result<Job, String> {
+        let index = self.find(spec)?;
+        Ok(self.jobs.remove(index))
+    }
+
+    fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%+");
+        let not_found = || format!("{}: no such job", spec);
+        let Some(name) = spec.strip_prefix('%') else { return Err(not_found()) };
+        match name {
+            "" | "%" | "+" => self.jobs.len().checked_sub(1).ok_or_else(|| String::from("no current job")),
+            "-" => self.jobs.len().checked_sub(2).ok_or_else(not_found),
+            _ => match name.parse::<usize>() {
+                Ok(id) => self.jobs.iter().position(|job| job.id == id),
+                Err(_) => self.jobs.iter().rposition(|job| job.command.starts_with(name))
+            }.ok_or_else(not_found)
+        }
+    }
+
+    /// The number of the job with `pid` among its processes.
+    pub fn find_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.pids.contains(&pid))
+            .map(|job| job.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn spawn(script: &str) -> pid_t {
+        Command::new("sh").args(["-c", script]).spawn().unwrap().id() as pid_t
+    }
+
+    #[test]
+    fn jobs_test_1() {
+        let mut table = JobTable::default();
+        let quick = spawn("exit 3");
+        let slow = spawn("sleep 5");
+        assert_eq!(table.add(Job::new(vec![quick], String::from("quick"))).id, 1);
+        assert_eq!(table.add(Job::new(vec![slow], String::from("slow"))).id, 2);
+
+        let mut finished = vec![];
+        for _ in 0..100 {
+            finished = table.reap();
+            if !finished.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].state, JobState::Done(3));
+        assert_eq!(finished[0].to_string(), format!("[1]  {:<24}quick", "Exit 3"));
+
+        process::kill(slow, libc::SIGKILL).unwrap();
+        let _ = process::wait_pid(slow);
+    }
+
+    #[test]
+    fn jobs_test_2() {
+        let mut table = JobTable::default();
+        for command in ["sleep 1", "cat", "sleep 2"] {
+            table.add(Job::new(vec![spawn("exit 0")], String::from(command)));
+        }
+        assert_eq!(table.take(Some("%2")).unwrap().command, "cat");
+        assert!(table.take(Some("%2")).is_err());
+        assert!(table.take(Some("2")).is_err());
+        assert_eq!(table.take(Some("%sleep")).unwrap().id, 3);
+
+        let mut job = table.take(None).unwrap();
+        assert_eq!(job.id, 1);
+        assert!(matches!(table.take(Some("%%")), Err(e) if e == "no current job"));
+        assert_eq!(job.wait(), 0);
+        table.add(job);
+        assert_eq!(table.iter().count(), 1);
+    }
+
+    #[test]
+    fn jobs_test_3() {
+        let pids = || vec![spawn("exit 2"), spawn("exit 3"), spawn("exit 0")];
+        let mut job = Job::new(pids(), String::new());
+        assert_eq!(job.wait(), 0);
+        assert_eq!(job.statuses(), vec![2, 3, 0]);
+
+        let mut job = Job::new(pids(), String::new());
+        job.pipefail = true;
+        assert_eq!(job.wait(), 3);
+        assert_eq!(job.state, JobState::Done(3));
+    }
+}
@@ -0,0 +1,1619 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::CommandExt;
+use std::path::{self, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+use crate::diagnostics::Renderer;
+use crate::editor::history::{Entry, History};
+use crate::parser::{ASTNode, ASTNodeType};
+use crate::tokenizer::token::{ExpansionOp, RedirectOp, Span};
+
+pub mod value;
+pub mod environment;
+pub mod jobs;
+mod process;
+mod pattern;
+use value::Value;
+use environment::{Environment, ScopeKind};
+use jobs::{Job, JobState, JobTable};
+
+/// Commands run by the shell itself.
+pub const BUILTINS: &[&str] = &["bg", "cd", "disown", "exit", "export", "false", "fg", "history", "jobs", "set", "true", "wait"];
+
+pub enum RuntimeError {
+    UndefinedVariable(String, Span),
+    InvalidOperands(String, &'static str, &'static str, Span),
+    CommandFailed(String, io::Error, Span),
+    ReturnOutsideFunction(Span),
+    UnsetParameter(String, String, Span),
+    RedirectFailed(String, io::Error, Span)
+}
+
+impl RuntimeError {
+    /// The source span of the node which caused the error.
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::UndefinedVariable(_, span)
+            | RuntimeError::InvalidOperands(_, _, _, span)
+            | RuntimeError::CommandFailed(_, _, span)
+            | RuntimeError::RedirectFailed(_, _, span)
+            | RuntimeError::ReturnOutsideFunction(span)
+            | RuntimeError::UnsetParameter(_, _, span) => *span
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable(name, _)
+                => write!(f, "Undefined variable {}", name),
+            RuntimeError::InvalidOperands(op, left, right, _)
+                => write!(f, "Cannot apply {} to {} and {}", op, left, right),
+            RuntimeError::CommandFailed(command, e, _) if e.kind() == io::ErrorKind::NotFound
+                => write!(f, "{}: command not found", command),
+            RuntimeError::CommandFailed(command, e, _)
+                => write!(f, "{}: {}", command, e),
+            RuntimeError::RedirectFailed(target, e, _)
+                => write!(f, "{}: {}", target, e),
+            RuntimeError::ReturnOutsideFunction(_)
+                => write!(f, "Cannot return outside of a function"),
+            RuntimeError::UnsetParameter(name, message, _) if message.is_empty()
+                => write!(f, "{}: parameter null or not set", name),
+            RuntimeError::UnsetParameter(name, message, _)
+                => write!(f, "{}: {}", name, message),
+        }
+    }
+}
+
+/// A user-defined function.
+#[derive(Clone)]
+struct Function {
+    params: Vec<String>,
+    body: Rc<ASTNode>,
+    /// The text the function was defined in, which its body's spans refer to.
+    source: Rc<str>
+}
+
+pub struct Interpreter {
+    env: Environment,
+    functions: HashMap<String, Function>,
+    jobs: JobTable,
+    /// The shell's process group, once job control is enabled.
+    shell_pgid: Option<process::pid_t>,
+    /// Whether the shell reads commands from a terminal, even if job control
+    /// couldn't be enabled. Background jobs are only announced if so.
+    interactive: bool,
+    /// The shell's terminal settings, restored after each foreground job.
+    terminal_modes: Option<libc::termios>,
+    /// Set by `return` until the enclosing function call picks it up.
+    return_value: Option<Value>,
+    last_status: i32,
+    /// Set with `set -o pipefail`, to make a pipeline fail if any stage fails.
+    pipefail: bool,
+    /// The lines entered at the prompt, which the `history` builtin lists.
+    history: History,
+    /// The text of the program being run, which errors are reported against.
+    source: Rc<str>,
+    /// The script's arguments, which `$@` expands to in a command, one word
+    /// each.
+    arguments: Vec<String>,
+    exit_requested: bool
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let mut env = Environment::new();
+        env.set("?", Value::Number(0.0));
+        Interpreter {
+            env,
+            functions: HashMap::new(),
+            jobs: JobTable::default(),
+            shell_pgid: None,
+            interactive: false,
+            terminal_modes: None,
+            return_value: None,
+            last_status: 0,
+            pipefail: false,
+            history: History::default(),
+            source: Rc::from(""),
+            arguments: vec![],
+            exit_requested: false
+        }
+    }
+
+    /// Enables job control for an interactive shell: each pipeline then
+    /// runs in a process group of its own, and foreground pipelines are
+    /// given the terminal while they run.
+    pub fn enable_job_control(&mut self) -> io::Result<()> {
+        self.interactive = true;
+        self.shell_pgid = Some(process::take_terminal()?);
+        self.terminal_modes = process::terminal_modes().ok();
+        Ok(())
+    }
+
+    /// Sets the text of the program about to be run, for reporting errors
+    /// which don't stop it.
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Rc::from(source);
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// The builtins and functions, which can be run by name as well as
+    /// programs.
+    pub fn command_names(&self) -> Vec<String> {
+        let builtins = BUILTINS.iter().map(|name| String::from(*name));
+        builtins.chain(self.functions.keys().cloned()).collect()
+    }
+
+    pub fn variable_names(&self) -> Vec<String> {
+        self.env.names()
+    }
+
+    /// The shell's `$PATH`, where commands are looked for.
+    pub fn path(&self) -> String {
+        self.env.get("PATH").map(|path| path.to_string()).unwrap_or_default()
+    }
+
+    /// Whether the `exit` builtin has been run.
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    /// The exit status of the last command, which is also the status the
+    /// shell exits with.
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// Sets the positional parameters of a script: `$0` to its name, `$1`
+    /// onwards to `args`, `$#` to their count, and `$@` and `$*` to all of
+    /// them separated by spaces.
+    pub fn set_arguments(&mut self, name: &str, args: &[String]) {
+        self.env.set("0", Value::String(String::from(name)));
+        for (index, arg) in args.iter().enumerate() {
+            self.env.set(&(index + 1).to_string(), Value::String(arg.clone()));
+        }
+        self.env.set("#", Value::Number(args.len() as f64));
+        for all in ["@", "*"] {
+            self.env.set(all, Value::String(args.join(" ")));
+        }
+        self.arguments = args.to_vec();
+    }
+
+    /// The value of the shell variable `name`, if it's set.
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+
+    /// Records the exit status of the last command, which `$?` expands to.
+    pub fn set_status(&mut self, status: i32) -> i32 {
+        self.last_status = status;
+        self.env.set("?", Value::Number(status as f64));
+        status
+    }
+
+    /// Records the status of each stage of the last foreground pipeline in
+    /// `PIPESTATUS`, separated by spaces.
+    fn set_pipe_status(&mut self, statuses: &[i32]) {
+        let statuses: Vec<String> = statuses.iter().map(i32::to_string).collect();
+        self.env.set("PIPESTATUS", Value::String(statuses.join(" ")));
+    }
+
+    pub fn exec(&mut self, node: &ASTNode) -> Result<Value, RuntimeError> {
+        match &node.node_type {
+            ASTNodeType::StatementList(statements) => self.exec_statements(statements),
+            ASTNodeType::Expression(inner) => self.exec(inner),
+            ASTNodeType::BinaryExpression(op, left, right) => {
+                let left = self.exec(left)?;
+                let right = self.exec(right)?;
+                binary_op(op, left, right, node.span)
+            },
+            ASTNodeType::VarDef(name, value) => {
+                let value = self.exec(value)?;
+                self.env.define(name, value);
+                Ok(Value::None)
+            },
+            ASTNodeType::Identifier(name) => self.lookup(name, node.span),
+            // Unlike identifiers, references to unset variables expand to nothing
+            ASTNodeType::VarRef(name) => Ok(self.env.get(name).unwrap_or(Value::None)),
+            ASTNodeType::ParamExpansion(name, op, words) => self.expand(name, *op, words, node.span),
+            ASTNodeType::Interpolation(parts) => {
+                let mut string = String::new();
+                for part in parts {
+                    string += &self.exec(part)?.to_string();
+                }
+                Ok(Value::String(string))
+            },
+            ASTNodeType::Quoted(part) => Ok(Value::String(pattern::escape(&self.exec(part)?.to_string()))),
+            ASTNodeType::StringLiteral(s) => Ok(Value::String(s.clone())),
+            ASTNodeType::NumberLiteral(n) => Ok(Value::Number(*n)),
+            ASTNodeType::BoolLiteral(b) => Ok(Value::Bool(*b)),
+            ASTNodeType::Command(..) => {
+                self.run_pipeline(std::slice::from_ref(node))?;
+                Ok(Value::None)
+            },
+            ASTNodeType::FnDef { name, params, body } => {
+                self.functions.insert(name.clone(), Function {
+                    params: params.clone(),
+                    body: Rc::clone(body),
+                    source: Rc::clone(&self.source)
+                });
+                Ok(Value::None)
+            },
+            ASTNodeType::Return(value) => {
+                if !self.env.in_function() {
+                    return Err(RuntimeError::ReturnOutsideFunction(node.span))
+                }
+                let value = match value {
+                    Some(value) => self.exec(value)?,
+                    None => Value::None
+                };
+                self.return_value = Some(value);
+                Ok(Value::None)
+            },
+            ASTNodeType::Redirect(..) => unreachable!("redirections are applied by their command"),
+            ASTNodeType::Pipeline(commands) => {
+                self.run_pipeline(commands)?;
+                Ok(Value::None)
+            },
+            ASTNodeType::AndOr(op, left, right) => {
+                self.exec(left)?;
+                if self.exit_requested || self.return_value.is_some() {
+                    return Ok(Value::None)
+                }
+                if (self.last_status == 0) == (op == "&&") {
+                    self.exec(right)?;
+                }
+                Ok(Value::None)
+            },
+            ASTNodeType::Background(pipeline) => {
+                self.run_background(pipeline)?;
+                Ok(Value::None)
+            },
+            ASTNodeType::Block(statements) => {
+                self.env.push_scope(ScopeKind::Block);
+                let result = self.exec_statements(statements);
+                self.env.pop_scope();
+                result
+            },
+            ASTNodeType::If { cond, then_block, else_branch } => {
+                if self.condition(cond)? {
+                    self.exec(then_block)
+                } else if let Some(else_branch) = else_branch {
+                    self.exec(else_branch)
+                } else {
+                    Ok(Value::None)
+                }
+            },
+        }
+    }
+
+    /// Runs statements in order, returning the value of the last one.
+    fn exec_statements(&mut self, statements: &[ASTNode]) -> Result<Value, RuntimeError> {
+        let mut result = Value::None;
+        for statement in statements {
+            result = self.exec(statement)?;
+            if self.exit_requested || self.return_value.is_some() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Evaluates the condition of an `if`. Commands are true when they exit
+    /// successfully, and anything else by the truthiness of its value.
+    fn condition(&mut self, cond: &ASTNode) -> Result<bool, RuntimeError> {
+        match &cond.node_type {
+            ASTNodeType::Command(..) => Ok(self.run_pipeline(std::slice::from_ref(cond))? == 0),
+            ASTNodeType::Pipeline(commands) => Ok(self.run_pipeline(commands)? == 0),
+            ASTNodeType::AndOr(..) => {
+                self.exec(cond)?;
+                Ok(self.last_status == 0)
+            },
+            _ => Ok(self.exec(cond)?.is_truthy())
+        }
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> Result<Value, RuntimeError> {
+        self.env
+            .get(name)
+            .ok_or_else(|| RuntimeError::UndefinedVariable(String::from(name), span))
+    }
+
+    /// Evaluates a `${...}` parameter expansion. Words are only evaluated
+    /// when they're used, so `${x:-$y}` doesn't expand `$y` if `x` is set.
+    fn expand(&mut self, name: &str, op: ExpansionOp, words: &[ASTNode], span: Span) -> Result<Value, RuntimeError> {
+        let value = self.env.get(name);
+        let text = value.as_ref().map_or(String::new(), Value::to_string);
+        let unset = |colon: bool| value.is_none() || (colon && text.is_empty());
+        match op {
+            ExpansionOp::Length => Ok(Value::Number(text.chars().count() as f64)),
+            ExpansionOp::Default(colon) if unset(colon) => Ok(Value::String(self.expand_word(words, 0)?)),
+            ExpansionOp::Assign(colon) if unset(colon) => {
+                let value = Value::String(self.expand_word(words, 0)?);
+                self.env.set(name, value.clone());
+                Ok(value)
+            },
+            ExpansionOp::Error(colon) if unset(colon)
+                => Err(RuntimeError::UnsetParameter(String::from(name), self.expand_word(words, 0)?, span)),
+            ExpansionOp::Default(_) | ExpansionOp::Assign(_) | ExpansionOp::Error(_)
+                => Ok(value.unwrap_or(Value::None)),
+            ExpansionOp::RemovePrefix(longest)
+                => Ok(Value::String(pattern::remove_prefix(&text, &self.expand_word(words, 0)?, longest))),
+            ExpansionOp::RemoveSuffix(longest)
+                => Ok(Value::String(pattern::remove_suffix(&text, &self.expand_word(words, 0)?, longest))),
+            ExpansionOp::Replace(all) => {
+                let from = self.expand_word(words, 0)?;
+                let to = self.expand_word(words, 1)?;
+                Ok(Value::String(pattern::replace(&text, &from, &to, all)))
+            }
+        }
+    }
+
+    /// The string value of a word of an expansion, or an empty string if
+    /// it was left out.
+    fn expand_word(&mut self, words: &[ASTNode], index: usize) -> Result<String, RuntimeError> {
+        match words.get(index) {
+            Some(word) => Ok(self.exec(word)?.to_string()),
+            None => Ok(String::new())
+        }
+    }
+
+    /// Evaluates the name and arguments of a command node to strings. A word
+    /// which is just `$@` or `"$@"` becomes one word per script argument.
+    fn command_args(&mut self, command: &ASTNode) -> Result<(String, Vec<String>), RuntimeError> {
+        let (name, args) = match &command.node_type {
+            ASTNodeType::Command(name, args, _) => (name, args),
+            _ => unreachable!("pipeline stages are always commands")
+        };
+        let mut words = vec![];
+        for word in std::iter::once(name.as_ref()).chain(args) {
+            if is_all_arguments(word) {
+                words.extend(self.arguments.iter().cloned());
+            } else {
+                words.push(self.exec(word)?.to_string());
+            }
+        }
+        let name = if words.is_empty() { String::new() } else { words.remove(0) };
+        Ok((name, words))
+    }
+
+    /// Evaluates the name and arguments of each command of a pipeline, once,
+    /// before any of them runs.
+    fn pipeline_args(&mut self, commands: &[ASTNode]) -> Result<Vec<(String, Vec<String>)>, RuntimeError> {
+        commands.iter().map(|command| self.command_args(command)).collect()
+    }
+
+    /**
+     *  Applies a command's redirections, in order, to `fds`: the files its
+     *  stdin, stdout and stderr will refer to, where `None` leaves the
+     *  shell's own in place.
+     */
+    fn redirect(&mut self, command: &ASTNode, fds: &mut [Option<OwnedFd>; 3]) -> Result<(), RuntimeError> {
+        let redirects = match &command.node_type {
+            ASTNodeType::Command(_, _, redirects) => redirects,
+            _ => unreachable!("pipeline stages are always commands")
+        };
+        for redirect in redirects {
+            let (op, target) = match &redirect.node_type {
+                ASTNodeType::Redirect(op, target) => (*op, target),
+                _ => unreachable!("redirections are always Redirect nodes")
+            };
+            let target = match target {
+                Some(target) => self.exec(target)?.to_string(),
+                None => op.to_string()
+            };
+            let failed = |e| match op {
+                RedirectOp::HereDoc(_) | RedirectOp::HereString(_)
+                    => RuntimeError::RedirectFailed(op.to_string(), e, redirect.span),
+                _ => RuntimeError::RedirectFailed(target.clone(), e, redirect.span)
+            };
+
+            let file = match op {
+                RedirectOp::HereDoc(_) => process::pipe_from(target.clone()),
+                RedirectOp::HereString(_) => process::pipe_from(format!("{}\n", target)),
+                RedirectOp::Input(_) => File::open(&target).map(OwnedFd::from),
+                RedirectOp::Output(_) | RedirectOp::All(false) => File::create(&target).map(OwnedFd::from),
+                RedirectOp::Append(_) | RedirectOp::All(true) => OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&target)
+                    .map(OwnedFd::from),
+                RedirectOp::Duplicate(_, source) => match fds.get(source as usize) {
+                    Some(Some(file)) => file.try_clone(),
+                    Some(None) => process::dup(source),
+                    None => Err(io::Error::from_raw_os_error(libc::EBADF))
+                }
+            }.map_err(failed)?;
+            let fd = match op {
+                RedirectOp::Input(fd)
+                | RedirectOp::HereDoc(fd)
+                | RedirectOp::HereString(fd)
+                | RedirectOp::Output(fd)
+                | RedirectOp::Append(fd)
+                | RedirectOp::Duplicate(fd, _) => fd,
+                RedirectOp::All(_) => {
+                    fds[2] = Some(file.try_clone().map_err(failed)?);
+                    1
+                }
+            };
+            match fds.get_mut(fd as usize) {
+                Some(slot) => *slot = Some(file),
+                None => return Err(failed(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only stdin, stdout and stderr can be redirected"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is run by the shell itself rather than as a program.
+    fn runs_in_process(&self, name: &str) -> bool {
+        self.functions.contains_key(name) || BUILTINS.contains(&name)
+    }
+
+    /// Runs a function or builtin in the shell process, returning its exit status.
+    fn run_in_process(&mut self, name: &str, args: &[String], span: Span) -> Result<i32, RuntimeError> {
+        if let Some(function) = self.functions.get(name).cloned() {
+            return self.call_function(&function, args, span)
+        }
+        match name {
+            "cd" => Ok(builtin_cd(args)),
+            "export" => Ok(self.builtin_export(args)),
+            "jobs" => Ok(self.builtin_jobs()),
+            "fg" => Ok(self.builtin_fg(args)),
+            "bg" => Ok(self.builtin_bg(args)),
+            "wait" => Ok(self.builtin_wait(args)),
+            "disown" => Ok(self.builtin_disown(args)),
+            "set" => Ok(self.builtin_set(args)),
+            "exit" => Ok(self.builtin_exit(args)),
+            "history" => Ok(self.builtin_history(args)),
+            "true" => Ok(0),
+            "false" => Ok(1),
+            _ => unreachable!("{} is not a builtin", name)
+        }
+    }
+
+    /**
+     *  Calls a user-defined function with its parameters bound to `args` in a
+     *  new scope. A returned bool becomes the exit status, and any other
+     *  returned value is written to stdout, so functions behave like
+     *  commands in pipelines.
+     */
+    fn call_function(&mut self, function: &Function, args: &[String], span: Span) -> Result<i32, RuntimeError> {
+        self.env.push_scope(ScopeKind::Function);
+        for (index, param) in function.params.iter().enumerate() {
+            let value = args.get(index).map_or(Value::None, |arg| Value::String(arg.clone()));
+            self.env.define(param, value);
+        }
+
+        let source = std::mem::replace(&mut self.source, Rc::clone(&function.source));
+        let result = self.exec(&function.body);
+        self.source = source;
+        self.env.pop_scope();
+        let returned = self.return_value.take();
+        result?;
+
+        match returned {
+            Some(Value::Bool(b)) => Ok(if b { 0 } else { 1 }),
+            Some(Value::None) | None => Ok(self.last_status),
+            Some(value) => {
+                let mut out = io::stdout();
+                if let Err(e) = writeln!(out, "{}", value).and_then(|_| out.flush()) {
+                    return Err(RuntimeError::CommandFailed(String::from("return"), e, span))
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /**
+     *  Runs each command of a pipeline, returning the exit status of the
+     *  last, or with `pipefail` of the last one which failed. A command
+     *  which can't be run, or whose redirections fail, is reported and
+     *  gives the status 127 if it wasn't found, or 1 otherwise, like any
+     *  other failing command. Other errors, such as `${name?}` on an unset
+     *  variable, still abort what's being run.
+     */
+    fn run_pipeline(&mut self, commands: &[ASTNode]) -> Result<i32, RuntimeError> {
+        let status = match self.pipeline_status(commands) {
+            Ok(status) => status,
+            Err(e @ (RuntimeError::CommandFailed(..) | RuntimeError::RedirectFailed(..))) => {
+                eprint!("{}", Renderer::for_stderr(&self.source).render("error", &e));
+                let status = match e {
+                    RuntimeError::CommandFailed(_, e, _) if e.kind() == io::ErrorKind::NotFound => 127,
+                    _ => 1
+                };
+                self.set_pipe_status(&[status]);
+                status
+            },
+            Err(e) => {
+                self.set_pipe_status(&[1]);
+                self.set_status(1);
+                return Err(e)
+            }
+        };
+        Ok(self.set_status(status))
+    }
+
+    fn pipeline_status(&mut self, commands: &[ASTNode]) -> Result<i32, RuntimeError> {
+        let argv = self.pipeline_args(commands)?;
+        // A lone function or builtin runs in the shell so it can change its state
+        if let ([command], [(name, args)]) = (commands, &argv[..]) {
+            if self.runs_in_process(name) {
+                let mut fds = [None, None, None];
+                self.redirect(command, &mut fds)?;
+                let _ = io::stdout().flush();
+                let saved = process::replace_std(&fds)
+                    .map_err(|e| RuntimeError::CommandFailed(name.clone(), e, command.span))?;
+                let status = self.run_in_process(name, args, command.span);
+                let _ = io::stdout().flush();
+                process::restore_std(saved);
+                if let Ok(status) = status {
+                    self.set_pipe_status(&[status]);
+                }
+                return status
+            }
+        }
+
+        let (pids, command) = self.spawn_pipeline(commands, argv)?;
+        let mut job = Job::new(pids, command);
+        job.pipefail = self.pipefail;
+        Ok(self.wait_foreground(job))
+    }
+
+    /**
+     *  Waits for a job to finish, with the terminal handed to the job's
+     *  process group meanwhile, and returns its exit status. The status
+     *  of each of its processes is recorded in `PIPESTATUS`. A job stopped
+     *  by Ctrl-Z is added to the job table instead, and its status is that
+     *  of a process stopped by SIGTSTP.
+     */
+    fn wait_foreground(&mut self, mut job: Job) -> i32 {
+        if self.shell_pgid.is_some() {
+            let _ = process::set_foreground(job.pgid);
+        }
+        if job.state == JobState::Stopped {
+            job.resume();
+        }
+        let state = job.wait_or_stop();
+        if let Some(shell_pgid) = self.shell_pgid {
+            let _ = process::set_foreground(shell_pgid);
+        }
+        if let Some(modes) = &self.terminal_modes {
+            let _ = process::set_terminal_modes(modes);
+        }
+
+        match state {
+            JobState::Done(status) => {
+                self.set_pipe_status(&job.statuses());
+                status
+            },
+            _ => {
+                eprintln!();
+                eprintln!("{}", self.jobs.add(job));
+                let status = 128 + libc::SIGTSTP;
+                self.set_pipe_status(&[status]);
+                status
+            }
+        }
+    }
+
+    /// Starts a pipeline as a background job, without waiting for it. Even a
+    /// lone builtin runs in a separate process, as it would in a pipeline,
+    /// and a `&&` or `||` chain runs in a forked copy of the shell.
+    fn run_background(&mut self, pipeline: &ASTNode) -> Result<i32, RuntimeError> {
+        let (pids, command) = match &pipeline.node_type {
+            ASTNodeType::AndOr(..) => {
+                let pgid = self.shell_pgid.map(|_| 0);
+                let pid = self.fork_shell("fork", pipeline.span, [None, None, None], pgid, |shell| {
+                    shell.exec(pipeline)?;
+                    Ok(shell.last_status)
+                })?;
+                (vec![pid], command_text(pipeline))
+            },
+            ASTNodeType::Pipeline(commands) => {
+                let argv = self.pipeline_args(commands)?;
+                self.spawn_pipeline(commands, argv)?
+            },
+            _ => {
+                let argv = self.pipeline_args(std::slice::from_ref(pipeline))?;
+                self.spawn_pipeline(std::slice::from_ref(pipeline), argv)?
+            }
+        };
+        let last_pid = *pids.last().unwrap();
+        let mut job = Job::new(pids, command);
+        job.pipefail = self.pipefail;
+        let job = self.jobs.add(job);
+        if self.interactive {
+            eprintln!("[{}] {}", job.id, last_pid);
+        }
+        self.env.set("!", Value::Number(last_pid as f64));
+        Ok(self.set_status(0))
+    }
+
+    /// Removes and returns the background jobs which have finished since the
+    /// last call, so they can be reported.
+    pub fn finished_jobs(&mut self) -> Vec<Job> {
+        self.jobs.reap()
+    }
+
+    /// Starts each command of a pipeline, connected by pipes, given the names
+    /// and arguments from `pipeline_args`. Returns the pids of the commands,
+    /// in order, and the pipeline's text for job listings. If a command
+    /// can't be started, those already running are killed and waited for.
+    fn spawn_pipeline(&mut self, commands: &[ASTNode], argv: Vec<(String, Vec<String>)>) -> Result<(Vec<process::pid_t>, String), RuntimeError> {
+        let mut pids = vec![];
+        let mut text = vec![];
+        if let Err(e) = self.spawn_stages(commands, argv, &mut pids, &mut text) {
+            for pid in pids {
+                let _ = process::kill(pid, libc::SIGKILL);
+                let _ = process::wait_pid(pid);
+            }
+            return Err(e)
+        }
+        Ok((pids, text.join(" | ")))
+    }
+
+    /// Starts the commands for `spawn_pipeline`, adding the pid and text of
+    /// each to `pids` and `text` as it goes.
+    fn spawn_stages(
+        &mut self,
+        commands: &[ASTNode],
+        argv: Vec<(String, Vec<String>)>,
+        pids: &mut Vec<process::pid_t>,
+        text: &mut Vec<String>
+    ) -> Result<(), RuntimeError> {
+        let mut previous_output: Option<OwnedFd> = None;
+        for (index, (stage, (name, args))) in commands.iter().zip(argv).enumerate() {
+            text.push(std::iter::once(&name).chain(&args).cloned().collect::<Vec<_>>().join(" "));
+            let (output, next_input) = if index + 1 < commands.len() {
+                let (read, write) = process::pipe()
+                    .map_err(|e| RuntimeError::CommandFailed(String::from("pipe"), e, stage.span))?;
+                (Some(write), Some(read))
+            } else {
+                (None, None)
+            };
+            let mut fds = [previous_output.take(), output, None];
+            self.redirect(stage, &mut fds)?;
+
+            // With job control, every stage joins the first one's process group
+            let pgid = self.shell_pgid.map(|_| pids.first().copied().unwrap_or(0));
+            let pid = if self.runs_in_process(&name) {
+                self.fork_shell(&name, stage.span, fds, pgid, |shell| shell.run_in_process(&name, &args, stage.span))?
+            } else {
+                let [stdin, stdout, stderr] = fds;
+                let mut command = Command::new(&name);
+                command
+                    .args(&args)
+                    .env_clear()
+                    .envs(self.env.exported())
+                    .stdin(stdin.map_or(Stdio::inherit(), Stdio::from))
+                    .stdout(stdout.map_or(Stdio::inherit(), Stdio::from))
+                    .stderr(stderr.map_or(Stdio::inherit(), Stdio::from));
+                if let Some(pgid) = pgid {
+                    command.process_group(pgid);
+                }
+                unsafe {
+                    command.pre_exec(|| {
+                        process::reset_signals();
+                        Ok(())
+                    });
+                }
+                let process = command.spawn();
+                match process {
+                    Ok(child) => child.id() as process::pid_t,
+                    Err(e) => return Err(RuntimeError::CommandFailed(name, e, stage.span))
+                }
+            };
+            pids.push(pid);
+            previous_output = next_input;
+        }
+        Ok(())
+    }
+
+    /// `exit [n]` makes the shell exit with status n, or with the status of
+    /// the last command.
+    fn builtin_exit(&mut self, args: &[String]) -> i32 {
+        self.exit_requested = true;
+        match args.first().map(|arg| arg.parse::<i32>()) {
+            None => self.last_status,
+            // Like the status of a process, it's only the lowest 8 bits
+            Some(Ok(status)) => status & 0xff,
+            Some(Err(_)) => {
+                eprintln!("exit: {}: numeric argument required", args[0]);
+                2
+            }
+        }
+    }
+
+    /**
+     *  `set -o name` turns on a shell option and `set +o name` turns it off.
+     *  Without a name, `set -o` lists the options and whether they're on,
+     *  and `set +o` lists the commands which would restore them. The only
+     *  option is `pipefail`.
+     */
+    fn builtin_set(&mut self, args: &[String]) -> i32 {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args[..] {
+            ["-o"] => println!("pipefail\t{}", if self.pipefail { "on" } else { "off" }),
+            ["+o"] => println!("set {}o pipefail", if self.pipefail { "-" } else { "+" }),
+            [flag @ ("-o" | "+o"), "pipefail"] => self.pipefail = flag == "-o",
+            ["-o" | "+o", option] => {
+                eprintln!("set: {}: invalid option name", option);
+                return 1
+            },
+            _ => {
+                eprintln!("set: usage: set [-o|+o] [option]");
+                return 2
+            }
+        }
+        0
+    }
+
+    /**
+     *  `history [count]` lists the lines entered at the prompt, or the last
+     *  `count` of them, with when and where each was entered, its status
+     *  and how long it ran for. `-s text` lists only those containing
+     *  `text`, `-p dir` those entered in `dir`, and `-f` those which failed.
+     *  `history -d number` deletes an entry.
+     */
+    fn builtin_history(&mut self, args: &[String]) -> i32 {
+        if let [flag, number] = args {
+            if flag == "-d" {
+                return self.delete_history(number)
+            }
+        }
+
+        let (mut failed, mut dir, mut text, mut count) = (false, None, None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.clone().next()) {
+                ("-f", _) => failed = true,
+                ("-p", Some(path)) => {
+                    args.next();
+                    dir = Some(match std::fs::canonicalize(path) {
+                        Ok(path) => path.to_string_lossy().into_owned(),
+                        Err(_) => path.clone()
+                    });
+                },
+                ("-s", Some(value)) => {
+                    args.next();
+                    text = Some(value);
+                },
+                (arg, _) if count.is_none() && arg.parse::<usize>().is_ok() => count = arg.parse().ok(),
+                _ => {
+                    eprintln!("history: usage: history [-f] [-p dir] [-s text] [count] | history -d number");
+                    return 2
+                }
+            }
+        }
+
+        let entries: Vec<(usize, &Entry)> = self.history.entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !failed || entry.status != 0)
+            .filter(|(_, entry)| dir.as_ref().is_none_or(|dir| entry.cwd == *dir))
+            .filter(|(_, entry)| text.is_none_or(|text| entry.line.contains(text.as_str())))
+            .collect();
+        let skip = entries.len().saturating_sub(count.unwrap_or(entries.len()));
+        for (index, entry) in &entries[skip..] {
+            println!("{}", format_entry(index + 1, entry));
+        }
+        0
+    }
+
+    /// `history -d number` deletes an entry from the history and its file.
+    fn delete_history(&mut self, number: &str) -> i32 {
+        match number.parse::<usize>() {
+            Ok(number @ 1..) if number <= self.history.len() => match self.history.remove(number - 1) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("history: can't write the history file: {}", e);
+                    1
+                }
+            },
+            _ => {
+                eprintln!("history: {}: position out of range", number);
+                1
+            }
+        }
+    }
+
+    /// `jobs` lists the shell's jobs, forgetting those which have finished.
+    fn builtin_jobs(&mut self) -> i32 {
+        self.jobs.poll();
+        for job in self.jobs.iter() {
+            println!("{}", job);
+        }
+        self.jobs.reap();
+        0
+    }
+
+    /// `fg [%job]` continues a job in the foreground and waits for it.
+    fn builtin_fg(&mut self, args: &[String]) -> i32 {
+        match self.jobs.take(args.first().map(String::as_str)) {
+            Ok(job) => {
+                println!("{}", job.command);
+                self.wait_foreground(job)
+            },
+            Err(e) => {
+                eprintln!("fg: {}", e);
+                1
+            }
+        }
+    }
+
+    /// `bg [%job]` continues a stopped job in the background.
+    fn builtin_bg(&mut self, args: &[String]) -> i32 {
+        match self.jobs.take(args.first().map(String::as_str)) {
+            Ok(mut job) => {
+                job.resume();
+                println!("[{}] {} &", job.id, job.command);
+                self.jobs.add(job);
+                0
+            },
+            Err(e) => {
+                eprintln!("bg: {}", e);
+                1
+            }
+        }
+    }
+
+    /**
+     *  `wait [%job|pid]...` waits for the given jobs or processes to finish,
+     *  returning the exit status of the last. Without arguments, it waits
+     *  for every job and returns 0.
+     */
+    fn builtin_wait(&mut self, args: &[String]) -> i32 {
+        if args.is_empty() {
+            while let Ok(mut job) = self.jobs.take(None) {
+                job.wait();
+            }
+            return 0
+        }
+        let mut status = 0;
+        for arg in args {
+            let spec = match arg.parse::<process::pid_t>() {
+                Ok(pid) => match self.jobs.find_pid(pid) {
+                    Some(id) => format!("%{}", id),
+                    // Not started as a job, but it may still be a child of the shell
+                    None => {
+                        status = process::wait_pid(pid).unwrap_or_else(|_| {
+                            eprintln!("wait: pid {} is not a child of this shell", pid);
+                            127
+                        });
+                        continue;
+                    }
+                },
+                Err(_) => arg.clone()
+            };
+            status = match self.jobs.take(Some(&spec)) {
+                Ok(mut job) => job.wait(),
+                Err(e) => {
+                    eprintln!("wait: {}", e);
+                    127
+                }
+            };
+        }
+        status
+    }
+
+    /// `disown [%job]...` stops tracking jobs, so they're no longer listed
+    /// or reported.
+    fn builtin_disown(&mut self, args: &[String]) -> i32 {
+        let specs: Vec<Option<&str>> = match args {
+            [] => vec![None],
+            args => args.iter().map(|arg| Some(arg.as_str())).collect()
+        };
+        let mut status = 0;
+        for spec in specs {
+            if let Err(e) = self.jobs.take(spec) {
+                eprintln!("disown: {}", e);
+                status = 1;
+            }
+        }
+        status
+    }
+
+    /// `export NAME[=VALUE]...` marks variables to be passed to child processes.
+    fn builtin_export(&mut self, args: &[String]) -> i32 {
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => {
+                    self.env.set(name, Value::String(String::from(value)));
+                    self.env.export(name);
+                },
+                None => self.env.export(arg)
+            }
+        }
+        0
+    }
+
+    /// Calls `run` in a forked copy of the shell, to run a function or
+    /// builtin as a pipeline stage or a chain in the background, with its
+    /// stdin, stdout and stderr replaced by `fds` where given. With a
+    /// `pgid`, the child joins that process group, or leads a new one if
+    /// it's 0. The child exits with the status `run` returns.
+    fn fork_shell(
+        &mut self,
+        name: &str,
+        span: Span,
+        fds: [Option<OwnedFd>; 3],
+        pgid: Option<process::pid_t>,
+        run: impl FnOnce(&mut Self) -> Result<i32, RuntimeError>
+    ) -> Result<process::pid_t, RuntimeError> {
+        // Anything still buffered would otherwise be written by both processes
+        let _ = io::stdout().flush();
+        match process::fork() {
+            Ok(Some(pid)) => {
+                // Set in both processes, so it's done before either relies on it
+                if let Some(pgid) = pgid {
+                    let _ = process::set_process_group(pid, if pgid == 0 { pid } else { pgid });
+                }
+                Ok(pid)
+            },
+            Ok(None) => {
+                if let Some(pgid) = pgid {
+                    let _ = process::set_process_group(0, pgid);
+                }
+                process::reset_signals();
+                self.shell_pgid = None;
+                self.interactive = false;
+                let status = match process::replace_std(&fds) {
+                    Ok(_) => match run(self) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            1
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error: {}: {}", name, e);
+                        1
+                    }
+                };
+                let _ = io::stdout().flush();
+                process::exit_child(status)
+            },
+            Err(e) => Err(RuntimeError::CommandFailed(String::from(name), e, span))
+        }
+    }
+}
+
+/// Whether `word` is `$@` or `"$@"` on its own.
+fn is_all_arguments(word: &ASTNode) -> bool {
+    match &word.node_type {
+        ASTNodeType::VarRef(name) => name == "@",
+        ASTNodeType::Interpolation(parts) => matches!(&parts[..], [part] if is_all_arguments(part)),
+        _ => false
+    }
+}
+
+/// The text of a command, pipeline or chain as written, without expanding
+/// variables, for job listings.
+fn command_text(node: &ASTNode) -> String {
+    let join = |nodes: &mut dyn Iterator<Item = &ASTNode>, separator: &str| nodes
+        .map(command_text)
+        .collect::<Vec<_>>()
+        .join(separator);
+    match &node.node_type {
+        ASTNodeType::Command(name, args, _) => join(&mut std::iter::once(&**name).chain(args), " "),
+        ASTNodeType::Pipeline(commands) => join(&mut commands.iter(), " | "),
+        ASTNodeType::AndOr(op, left, right) => format!("{} {} {}", command_text(left), op, command_text(right)),
+        ASTNodeType::Interpolation(parts) => join(&mut parts.iter(), ""),
+        ASTNodeType::Quoted(part) => format!("\"{}\"", command_text(part)),
+        ASTNodeType::StringLiteral(s) => s.clone(),
+        ASTNodeType::VarRef(name) => format!("${}", name),
+        ASTNodeType::ParamExpansion(name, ExpansionOp::Length, _) => format!("${{#{}}}", name),
+        ASTNodeType::ParamExpansion(name, op, words) => format!("${{{}{}{}}}", name, op.as_str(), join(&mut words.iter(), "/")),
+        _ => String::new()
+    }
+}
+
+/// A history entry as the `history` builtin lists it. Entries from before
+/// the history kept metadata show only their line.
+fn format_entry(number: usize, entry: &Entry) -> String {
+    if entry.time == 0 {
+        return format!("{:>5}  {}", number, entry.line)
+    }
+    let duration = match entry.duration / 1000 {
+        seconds if seconds >= 60 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{:.1}s", entry.duration as f64 / 1000.0)
+    };
+    format!(
+        "{:>5}  {}  {:>3}  {:>7}  {}  {}",
+        number, local_time(entry.time), entry.status, duration, entry.cwd, entry.line
+    )
+}
+
+/// Formats seconds since the Unix epoch as a date and time in the local
+/// time zone.
+fn local_time(time: u64) -> String {
+    let time = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::from("?")
+    }
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec
+    )
+}
+
+fn builtin_cd(args: &[String]) -> i32 {
+    // default to '~/' as new directory if one was not provided
+    // default to '/' if home dir doesn't exist
+    let home = match home::home_dir() {
+        Some(home_dir) => home_dir,
+        None => PathBuf::from("/")
+    };
+    let new_dir = args
+        .first()
+        .map_or(home.to_str().unwrap_or("/"), |x| x.as_str());
+    let new_dir = path::Path::new(new_dir);
+    if let Err(e) = env::set_current_dir(new_dir) {
+        eprintln!("cd: {}", e);
+        return 1
+    }
+    0
+}
+
+fn binary_op(op: &str, left: Value, right: Value, span: Span) -> Result<Value, RuntimeError> {
+    if let (Some(l), Some(r)) = (left.as_number(), right.as_number()) {
+        return match op {
+            "+" => Ok(Value::Number(l + r)),
+            "-" => Ok(Value::Number(l - r)),
+            "*" => Ok(Value::Number(l * r)),
+            "/" => Ok(Value::Number(l / r)),
+            "==" => Ok(Value::Bool(l == r)),
+            "!=" => Ok(Value::Bool(l != r)),
+            "<" => Ok(Value::Bool(l < r)),
+            ">" => Ok(Value::Bool(l > r)),
+            "<=" => Ok(Value::Bool(l <= r)),
+            ">=" => Ok(Value::Bool(l >= r)),
+            _ => unreachable!("unknown binary operator {}", op)
+        }
+    }
+    // Anything that isn't a pair of numbers compares by its text
+    let (l, r) = (left.to_string(), right.to_string());
+    match op {
+        "==" => return Ok(Value::Bool(l == r)),
+        "!=" => return Ok(Value::Bool(l != r)),
+        "<" => return Ok(Value::Bool(l < r)),
+        ">" => return Ok(Value::Bool(l > r)),
+        "<=" => return Ok(Value::Bool(l <= r)),
+        ">=" => return Ok(Value::Bool(l >= r)),
+        _ => {}
+    }
+    match (op, &left, &right) {
+        ("+", Value::String(_), _) | ("+", _, Value::String(_))
+            => Ok(Value::String(format!("{}{}", left, right))),
+        _ => Err(RuntimeError::InvalidOperands(String::from(op), left.type_name(), right.type_name(), span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn eval(input: &str) -> Value {
+        let mut p = Parser::new();
+        let mut i = Interpreter::new();
+        match i.exec(&p.parse(input).unwrap()) {
+            Ok(value) => value,
+            Err(e) => panic!("{}", e)
+        }
+    }
+
+    #[test]
+    fn interpreter_test_1() {
+        assert_eq!(eval("2 + 2 * 3"), Value::Number(8.0));
+        assert_eq!(eval("(2 + 2) * 3"), Value::Number(12.0));
+    }
+
+    #[test]
+    fn interpreter_test_2() {
+        assert_eq!(eval("let x = 10 let y = x / 4 (y)"), Value::Number(2.5));
+    }
+
+    #[test]
+    fn interpreter_test_3() {
+        let mut i = Interpreter::new();
+        let mut p = Parser::new();
+        assert!(i.exec(&p.parse("exit").unwrap()).is_ok());
+        assert!(i.exit_requested());
+    }
+
+    #[test]
+    fn interpreter_test_4() {
+        assert_eq!(eval("if 1 == 2 { 1 } else if \"a\" < \"b\" { 2 } else { 3 }"), Value::Number(2.0));
+        assert_eq!(eval("if false { 1 }"), Value::None);
+        assert_eq!(eval("if sh -c \"exit 3\" { 1 } else { 2 }"), Value::Number(2.0));
+        assert_eq!(eval("if sh -c \"exit 0\" { 1 } else { 2 }"), Value::Number(1.0));
+    }
+
+    #[test]
+    fn interpreter_test_5() {
+        let mut p = Parser::new();
+        let mut i = Interpreter::new();
+        i.exec(&p.parse("fn add(a, b) { let c = a + b return c } let c = 1").unwrap()).ok();
+        assert!(i.exec(&p.parse("add 2 3").unwrap()).is_ok());
+        assert_eq!(i.last_status, 0);
+        assert_eq!(i.exec(&p.parse("(c)").unwrap()).ok(), Some(Value::Number(1.0)));
+
+        i.exec(&p.parse("fn check(x) { if (x == 1) { return true } return false }").unwrap()).ok();
+        i.exec(&p.parse("check 2").unwrap()).ok();
+        assert_eq!(i.last_status, 1);
+        i.exec(&p.parse("check 1").unwrap()).ok();
+        assert_eq!(i.last_status, 0);
+
+        assert!(matches!(i.exec(&p.parse("return 1").unwrap()), Err(RuntimeError::ReturnOutsideFunction(_))));
+    }
+
+    #[test]
+    fn interpreter_test_6() {
+        let mut p = Parser::new();
+        let mut i = Interpreter::new();
+        i.exec(&p.parse("fn greet(name) { return name + 1 }").unwrap()).ok();
+        let program = p.parse("if greet 41 | grep -q 42 { 1 } else { 2 }").unwrap();
+        assert_eq!(i.exec(&program).ok(), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn interpreter_test_7() {
+        let mut p = Parser::new();
+        let mut i = Interpreter::new();
+        let program = p.parse("let RUSH_TEST_VAR = 1 if sh -c 'test -n \"$RUSH_TEST_VAR\"' { 1 } else { 2 }").unwrap();
+        assert_eq!(i.exec(&program).ok(), Some(Value::Number(2.0)));
+        i.exec(&p.parse("export RUSH_TEST_VAR").unwrap()).ok();
+        let program = p.parse("if sh -c 'test -n \"$RUSH_TEST_VAR\"' { 1 } else { 2 }").unwrap();
+        assert_eq!(i.exec(&program).ok(), Some(Value::Number(1.0)));
+        let program = p.parse("if true { let scoped = 1 } (scoped)").unwrap();
+        assert!(matches!(i.exec(&program), Err(RuntimeError::UndefinedVariable(..))));
+    }
+
+    #[test]
+    fn interpreter_test_8() {
+        assert_eq!(eval("let x = 2 \"x is $x, \\$x\""), Value::String(String::from("x is 2, $x")));
+        assert_eq!(eval("let x = 2 $x * 3"), Value::Number(6.0));
+        assert_eq!(eval("\"[$rush_unset_variable]\""), Value::String(String::from("[]")));
+        assert_eq!(eval("let x = 2 if sh -c 'exit $0' $x { 1 } else { 2 }"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn interpreter_test_9() {
+        let mut i = Interpreter::new();
+        let mut eval = |input: &str| i.exec(&Parser::new().parse(input).unwrap());
+        eval("let path = \"/usr/local/lib/libfoo.so.1\"").ok();
+        let string = |s: &str| Value::String(String::from(s));
+        assert_eq!(eval("${path##*/}").ok(), Some(string("libfoo.so.1")));
+        assert_eq!(eval("\"${path%/*}\"").ok(), Some(string("/usr/local/lib")));
+        assert_eq!(eval("${path%%.*}").ok(), Some(string("/usr/local/lib/libfoo")));
+        assert_eq!(eval("${path#/usr}").ok(), Some(string("/local/lib/libfoo.so.1")));
+        assert_eq!(eval("${path//l/L}").ok(), Some(string("/usr/LocaL/Lib/Libfoo.so.1")));
+        assert_eq!(eval("${path/lib/}").ok(), Some(string("/usr/local//libfoo.so.1")));
+        assert_eq!(eval("${#path}").ok(), Some(Value::Number(26.0)));
+
+        assert_eq!(eval("${rush_unset:-$path}").ok(), Some(string("/usr/local/lib/libfoo.so.1")));
+        assert_eq!(eval("${path:-default}").ok(), Some(string("/usr/local/lib/libfoo.so.1")));
+        assert_eq!(eval("${rush_unset:=set now}").ok(), Some(string("set now")));
+        assert_eq!(eval("$rush_unset").ok(), Some(string("set now")));
+
+        eval("let empty = \"\"").ok();
+        assert_eq!(eval("${empty-kept}").ok(), Some(string("")));
+        assert_eq!(eval("${empty:-replaced}").ok(), Some(string("replaced")));
+        // Quotes in words are removed, and make a pattern match literally
+        let _ = eval("let glob = \"a*b\"; let other = \"aXb\"; let p = \"a*\"");
+        assert_eq!(eval("${rush_none:-\"a b\"}").ok(), Some(string("a b")));
+        assert_eq!(eval("${rush_none:-'a b'}").ok(), Some(string("a b")));
+        assert_eq!(eval("${rush_none:-a\\ \"}\"}").ok(), Some(string("a }")));
+        assert_eq!(eval("${glob#\"a*\"}").ok(), Some(string("b")));
+        assert_eq!(eval("${other#\"a*\"}").ok(), Some(string("aXb")));
+        assert_eq!(eval("${other#'a*'}").ok(), Some(string("aXb")));
+        assert_eq!(eval("${other#a*}").ok(), Some(string("Xb")));
+        assert_eq!(eval("${other#\"$p\"}").ok(), Some(string("aXb")));
+        assert_eq!(eval("${other#$p}").ok(), Some(string("Xb")));
+        assert_eq!(eval("${glob/'*'/-}").ok(), Some(string("a-b")));
+        match eval("${rush_missing:?is required}") {
+            Err(e) => assert_eq!(e.to_string(), "rush_missing: is required"),
+            Ok(_) => panic!()
+        }
+    }
+
+    #[test]
+    fn interpreter_test_10() {
+        let path = env::temp_dir().join(format!("rush_redirect_test_{}", std::process::id()));
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        run(&format!("let file = \"{}\"", path.display()));
+
+        run("sh -c 'echo out; echo err >&2' > $file 2>&1");
+        run("echo more >> $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\nmore\n");
+
+        run("sh -c 'echo out; echo err >&2' 2> $file > /dev/null");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "err\n");
+
+        run("sh -c 'echo out; echo err >&2' &> $file");
+        run("fn greet() { return \"hello\" }");
+        run("greet >> $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\nhello\n");
+
+        assert_eq!(run("if grep -q hello < $file { 1 } else { 2 }"), Some(Value::Number(1.0)));
+        assert_eq!(run("if greet | grep -q hello > /dev/null < $file { 1 } else { 2 }"), Some(Value::Number(1.0)));
+        let _ = std::fs::remove_file(&path);
+
+        // A failed redirection is reported, and fails the command like any other error
+        assert_eq!(run("cat < /rush/missing/file"), Some(Value::None));
+        assert_eq!(run("$?"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn interpreter_test_11() {
+        let path = env::temp_dir().join(format!("rush_heredoc_test_{}", std::process::id()));
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        run(&format!("let file = \"{}\"", path.display()));
+        run("let name = \"rush\"");
+
+        run("cat > $file <<EOF\nhello $name\n\\$name ${name%sh}\nEOF\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello rush\n$name ru\n");
+
+        run("cat <<-'EOF' > $file\n\t\tquoted $name\n\tEOF\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "quoted $name\n");
+
+        run("cat <<< \"$name here\" > $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "rush here\n");
+
+        let long = "x".repeat(100_000);
+        run(&format!("cat > $file <<EOF\n{}\nEOF\n", long));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), long + "\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn interpreter_test_12() {
+        let mut i = Interpreter::new();
+        let program = Parser::new().parse("sh -c 'exit 4' | cat & fn f() { return false } f &").unwrap();
+        i.exec(&program).ok();
+        let pid = match i.env.get("!") {
+            Some(Value::Number(pid)) => pid,
+            _ => panic!()
+        };
+        assert!(pid > 0.0);
+
+        let mut finished = vec![];
+        for _ in 0..100 {
+            finished.extend(i.finished_jobs());
+            if finished.len() == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        finished.sort_by_key(|job| job.id);
+        assert_eq!(finished[0].command, "sh -c exit 4 | cat");
+        assert_eq!(finished[0].state, jobs::JobState::Done(0));
+        assert_eq!(finished[1].command, "f");
+        assert_eq!(finished[1].state, jobs::JobState::Done(1));
+        assert_eq!(*finished[1].pids.last().unwrap() as f64, pid);
+    }
+
+    #[test]
+    fn interpreter_test_13() {
+        let mut i = Interpreter::new();
+        let run = |i: &mut Interpreter, input: &str| {
+            i.exec(&Parser::new().parse(input).unwrap()).ok();
+            i.last_status
+        };
+        run(&mut i, "sh -c 'exit 5' &");
+        assert_eq!(run(&mut i, "wait $!"), 5);
+        assert_eq!(run(&mut i, "wait %1"), 127);
+
+        run(&mut i, "sleep 0.3 &");
+        let pid = i.jobs.iter().next().unwrap().pgid;
+        process::kill(pid, libc::SIGSTOP).unwrap();
+        let mut stopped = false;
+        for _ in 0..100 {
+            i.jobs.poll();
+            if i.jobs.iter().next().unwrap().state == JobState::Stopped {
+                stopped = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(stopped);
+        assert_eq!(run(&mut i, "bg %sleep"), 0);
+        assert_eq!(i.jobs.iter().next().unwrap().state, JobState::Running);
+        assert_eq!(run(&mut i, "fg"), 0);
+        assert_eq!(i.jobs.iter().count(), 0);
+        assert_eq!(run(&mut i, "fg"), 1);
+
+        run(&mut i, "sh -c 'exit 2' &");
+        assert_eq!(run(&mut i, "disown %1"), 0);
+        assert_eq!(run(&mut i, "disown %1"), 1);
+        assert_eq!(run(&mut i, "wait"), 0);
+    }
+
+    #[test]
+    fn interpreter_test_14() {
+        let mut i = Interpreter::new();
+        let run = |i: &mut Interpreter, input: &str| {
+            i.exec(&Parser::new().parse(input).unwrap()).ok();
+            i.last_status
+        };
+        assert_eq!(run(&mut i, "sh -c 'kill -STOP $$; exit 4'"), 128 + libc::SIGTSTP);
+        let job = i.jobs.iter().next().unwrap();
+        assert_eq!((job.id, job.state), (1, JobState::Stopped));
+        assert_eq!(run(&mut i, "fg %1"), 4);
+        assert_eq!(i.jobs.iter().count(), 0);
+    }
+
+    #[test]
+    fn interpreter_test_15() {
+        let path = env::temp_dir().join(format!("rush_chain_test_{}", std::process::id()));
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        run(&format!("let file = \"{}\"", path.display()));
+
+        run("test a = b && echo skipped > $file || echo failed > $file");
+        run("test a = a || echo skipped >> $file && echo passed >> $file");
+        run("echo hi | grep -q hi && echo piped >> $file");
+        run("fn f() { grep -q missing $file || echo called >> $file }");
+        run("f");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "failed\npassed\npiped\ncalled\n");
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(run("if test a && test \"\" { 1 } else { 2 }"), Some(Value::Number(2.0)));
+        assert_eq!(run("if test \"\" || test a { 1 } else { 2 }"), Some(Value::Number(1.0)));
+
+        run("let code = 6");
+        run("test \"\" || sh -c \"exit $code\" &");
+        let job = i.jobs.iter().next().unwrap();
+        assert_eq!(job.command, "test  || sh -c exit $code");
+        assert_eq!(job.clone().wait(), 6);
+    }
+
+    #[test]
+    fn interpreter_test_16() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("sh -c 'exit 3'");
+        assert_eq!(run("$?"), Some(Value::Number(3.0)));
+        run("sh -c 'kill -TERM $$'");
+        assert_eq!(run("\"status $?\""), Some(Value::String(String::from("status 143"))));
+        run("rush-missing-command");
+        assert_eq!(run("$?"), Some(Value::Number(127.0)));
+        run("sh -c 'exit 2' || sh -c \"exit $?$?\"");
+        assert_eq!(run("$?"), Some(Value::Number(22.0)));
+
+        run("sh -c 'exit 5'");
+        run("exit");
+        assert!(i.exit_requested());
+        assert_eq!(i.last_status(), 5);
+        let mut i = Interpreter::new();
+        i.exec(&Parser::new().parse("exit 260").unwrap()).ok();
+        assert_eq!(i.last_status(), 4);
+    }
+
+    #[test]
+    fn interpreter_test_17() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        let pipeline = "sh -c 'exit 3' | sh -c 'cat; exit 4' | cat";
+        run(pipeline);
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        assert_eq!(run("$PIPESTATUS"), Some(Value::String(String::from("3 4 0"))));
+
+        run("set -o pipefail");
+        run(pipeline);
+        assert_eq!(run("$?"), Some(Value::Number(4.0)));
+        run("echo a | cat");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("sh -c 'exit 5'");
+        assert_eq!(run("$PIPESTATUS"), Some(Value::String(String::from("5"))));
+
+        run("set +o pipefail");
+        run(pipeline);
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("set -o nounset");
+        assert_eq!(run("$?"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn interpreter_test_18() {
+        let path = env::temp_dir().join(format!("rush_pipe_all_test_{}", std::process::id()));
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        run(&format!("let file = \"{}\"", path.display()));
+
+        run("sh -c 'echo out; echo err >&2' 2> /dev/null | cat > $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\n");
+        run("sh -c 'echo out; echo err >&2' |& cat > $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "out\nerr\n");
+        // The implicit `2>&1` comes after the command's own redirections
+        run("sh -c 'echo out; echo err >&2' > /dev/null |& cat > $file");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn interpreter_test_19() {
+        let mut i = Interpreter::new();
+        i.set_arguments("script.rush", &[String::from("a"), String::from("b c")]);
+        let program = Parser::new().parse("#!/usr/bin/env rush\n\"$0: $# [$1] [$2] [$3] [$@]\"\n").unwrap();
+        assert_eq!(i.exec(&program).ok(), Some(Value::String(String::from("script.rush: 2 [a] [b c] [] [a b c]"))));
+    }
+
+    #[test]
+    fn interpreter_test_20() {
+        let mut i = Interpreter::new();
+        for (line, status) in [("git pull", 0), ("make", 2), ("git push", 1), ("ls", 0)] {
+            i.history_mut().add(Entry { line: String::from(line), status, ..Entry::default() }).unwrap();
+        }
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        run("history -f -p / -s git 2");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("history -x");
+        assert_eq!(run("$?"), Some(Value::Number(2.0)));
+        run("history -d 9");
+        assert_eq!(run("$?"), Some(Value::Number(1.0)));
+        run("history -d 2");
+        assert_eq!(i.history().get(1), Some("git push"));
+
+        let entry = Entry {
+            line: String::from("cargo build"),
+            time: 0,
+            cwd: String::from("/src"),
+            duration: 83500,
+            status: 101
+        };
+        assert_eq!(format_entry(12, &entry), "   12  cargo build");
+        let entry = Entry { time: 1, ..entry };
+        assert!(format_entry(12, &entry).ends_with("  101    1m23s  /src  cargo build"));
+        let entry = Entry { duration: 250, ..entry };
+        assert!(format_entry(12, &entry).ends_with("  101     0.2s  /src  cargo build"));
+    }
+
+    #[test]
+    fn interpreter_test_21() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // A command which can't be run fails with a status rather than an error
+        assert_eq!(run("if rush_missing_command { 1 } else { 2 }"), Some(Value::Number(2.0)));
+        assert_eq!(run("$?"), Some(Value::Number(127.0)));
+        run("rush_missing_command || sh -c 'exit 3'");
+        assert_eq!(run("$?"), Some(Value::Number(3.0)));
+        run("rush_missing_command && sh -c 'exit 3'");
+        assert_eq!(run("$?"), Some(Value::Number(127.0)));
+    }
+
+    #[test]
+    fn interpreter_test_22() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // The left side of `&&` and `||` fails without ending the statement
+        run("cat < /rush/missing/file || sh -c 'exit 3'");
+        assert_eq!(run("$?"), Some(Value::Number(3.0)));
+        run("false && sh -c 'exit 3'");
+        assert_eq!(run("$?"), Some(Value::Number(1.0)));
+        run("true && false || sh -c 'exit 4'");
+        assert_eq!(run("$?"), Some(Value::Number(4.0)));
+        assert_eq!(run("if false || true { 1 } else { 2 }"), Some(Value::Number(1.0)));
+        // On their own they're commands too, which set the status
+        assert_eq!(run("false"), Some(Value::None));
+        assert_eq!(run("$?"), Some(Value::Number(1.0)));
+        run("true");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        // But they're booleans in expressions
+        assert_eq!(run("let b = false; $b"), Some(Value::Bool(false)));
+        assert_eq!(run("true == false"), Some(Value::Bool(false)));
+    }
+
+    #[test]
+    fn interpreter_test_23() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // A here-document bigger than a pipe's buffer, read by a forked function
+        run("fn count() { sh -c 'test $(wc -c) = 10001' }");
+        run(&format!("cat <<EOF | count\n{}\nEOF", "x".repeat(10000)));
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+    }
+
+    #[test]
+    fn interpreter_test_24() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        let script = "
+            let x = 2
+            sh -c 'exit 3'
+            let status = $?
+            let y = x *
+                status
+            if $y == 6 {
+                sh -c 'exit 4'
+            }
+            else { 1 }
+        ";
+        run(script);
+        assert_eq!(run("$status"), Some(Value::Number(3.0)));
+        assert_eq!(run("$?"), Some(Value::Number(4.0)));
+    }
+
+    #[test]
+    fn interpreter_test_25() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // Each word is expanded once, so assignments happen in order
+        run("sh -c 'test \"$*\" = \"unset 1 1\"' sh ${x-unset} ${x=1} $x");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("sh -c 'test \"$*\" = \"2 2\"' sh ${y=2} $y | cat");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+    }
+
+    #[test]
+    fn interpreter_test_26() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // A variable can name the command to run
+        run("let e = \"echo\"; $e hello");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("let x = \"sh\"; $x -c 'exit 5'");
+        assert_eq!(run("$?"), Some(Value::Number(5.0)));
+        run("let x = \"test\"; $x /");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        assert_eq!(run("let n = 6; $n / 2"), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn interpreter_test_27() {
+        let mut i = Interpreter::new();
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        let path = env::temp_dir().join(format!("rush_spawn_test_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        run(&format!("let file = \"{}\"", path.display()));
+        // The first stage is killed when the second can't be started
+        run("sh -c 'sleep 0.5; touch \"$1\"' sh $file | rush_missing_command");
+        assert_eq!(run("$?"), Some(Value::Number(127.0)));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn interpreter_test_28() {
+        let mut i = Interpreter::new();
+        i.set_arguments("script.rush", &[String::from("a"), String::from("b c")]);
+        let mut run = |input: &str| i.exec(&Parser::new().parse(input).unwrap()).ok();
+        // Each argument is a word of its own, quoted or not
+        run("sh -c 'test $# = 2 && test \"$2\" = \"b c\"' sh $@");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        run("sh -c 'test $# = 3 && test \"$3\" = \"b c\"' sh x \"$@\"");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+        // Within a longer word it's still joined
+        run("sh -c 'test $# = 1' sh \"[$@]\"");
+        assert_eq!(run("$?"), Some(Value::Number(0.0)));
+    }
+}
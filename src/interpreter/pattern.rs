@@ -0,0 +1,112 @@
+/// Whether all of `text` matches the glob `pattern`, where `*` matches any
+/// run of characters, `?` any one character and a backslash makes the next
+/// character match only itself.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| matches_chars(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && matches_chars(rest, &text[1..]),
+        Some(('\\', [escaped, rest @ ..])) => text.first() == Some(escaped) && matches_chars(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && matches_chars(rest, &text[1..])
+    }
+}
+
+/// Escapes the characters of `text` which are special in a pattern, so
+/// that it only matches itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The byte offsets of every character boundary in `text`, including its end.
+fn boundaries(text: &str) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    text.char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(text.len()))
+}
+
+/// Removes the shortest, or with `longest` the longest, prefix of `text`
+/// matching `pattern`.
+pub fn remove_prefix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut ends: Vec<usize> = boundaries(text).collect();
+    if longest {
+        ends.reverse();
+    }
+    let end = ends.into_iter().find(|end| matches(pattern, &text[..*end]));
+    String::from(end.map_or(text, |end| &text[end..]))
+}
+
+/// Removes the shortest, or with `longest` the longest, suffix of `text`
+/// matching `pattern`.
+pub fn remove_suffix(text: &str, pattern: &str, longest: bool) -> String {
+    let mut starts: Vec<usize> = boundaries(text).collect();
+    if !longest {
+        starts.reverse();
+    }
+    let start = starts.into_iter().find(|start| matches(pattern, &text[*start..]));
+    String::from(start.map_or(text, |start| &text[..start]))
+}
+
+/// Replaces the longest match of `pattern` in `text` which starts first, or
+/// with `all` every non-overlapping match, with `replacement`.
+pub fn replace(text: &str, pattern: &str, replacement: &str, all: bool) -> String {
+    let bounds: Vec<usize> = boundaries(text).collect();
+    let mut result = String::new();
+    let mut copied = 0;
+    let mut index = 0;
+    while index < bounds.len() {
+        let start = bounds[index];
+        let end = bounds[index + 1..]
+            .iter()
+            .rposition(|end| matches(pattern, &text[start..*end]));
+        match end {
+            Some(offset) => {
+                result += &text[copied..start];
+                result += replacement;
+                index += offset + 1;
+                copied = bounds[index];
+                if !all {
+                    break;
+                }
+            },
+            None => index += 1
+        }
+    }
+    result + &text[copied..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_test_1() {
+        assert!(matches("*.t?t", "notes.txt"));
+        assert!(!matches("*.t?t", "notes.tt"));
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+
+        assert_eq!(remove_prefix("/usr/local/bin", "*/", false), "usr/local/bin");
+        assert_eq!(remove_prefix("/usr/local/bin", "*/", true), "bin");
+        assert_eq!(remove_suffix("archive.tar.gz", ".*", false), "archive.tar");
+        assert_eq!(remove_suffix("archive.tar.gz", ".*", true), "archive");
+        assert_eq!(remove_suffix("naïve", "?", false), "naïv");
+
+        assert_eq!(replace("a-b-c", "-", "+", false), "a+b-c");
+        assert_eq!(replace("a-b-c", "-", "+", true), "a+b+c");
+        assert_eq!(replace("a-b-c", "b*", "x", false), "a-x");
+        assert_eq!(replace("abc", "", "x", true), "abc");
+    }
+}
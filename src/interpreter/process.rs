@@ -0,0 +1,221 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub use libc::pid_t;
+
+/// Creates a pipe, returning its read and write ends. Both ends are closed
+/// on exec, so they only reach a child through an explicit redirection.
+pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds: [libc::c_int; 2] = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    unsafe { Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]))) }
+}
+
+/// Opens a file which reads back `text`, for here-documents. Short text is
+/// written into a pipe. Text that might not fit in the pipe's buffer goes
+/// in an unlinked temporary file instead, so nothing is left holding a
+/// write end which children forked meanwhile would inherit.
+pub fn pipe_from(text: String) -> io::Result<OwnedFd> {
+    if text.len() <= libc::PIPE_BUF {
+        let (read, write) = pipe()?;
+        File::from(write).write_all(text.as_bytes())?;
+        return Ok(read)
+    }
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "rush-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(text.as_bytes())?;
+    file.rewind()?;
+    Ok(OwnedFd::from(file))
+}
+
+/// Forks the shell, returning the pid of the child in the parent and `None`
+/// in the child.
+pub fn fork() -> io::Result<Option<pid_t>> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        pid => Ok(Some(pid))
+    }
+}
+
+/// Makes `target` refer to the same file as `fd`.
+pub fn redirect(fd: &OwnedFd, target: RawFd) -> io::Result<()> {
+    if unsafe { libc::dup2(fd.as_raw_fd(), target) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Duplicates the shell's own `fd`. The copy is closed on exec.
+pub fn dup(fd: RawFd) -> io::Result<OwnedFd> {
+    unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()
+}
+
+/// Points each of stdin, stdout and stderr at the file given for it in
+/// `fds`, returning copies of the replaced descriptors for `restore_std`.
+pub fn replace_std(fds: &[Option<OwnedFd>; 3]) -> io::Result<Vec<(RawFd, OwnedFd)>> {
+    let mut saved = vec![];
+    for (target, fd) in fds.iter().enumerate() {
+        let Some(fd) = fd else { continue };
+        let target = target as RawFd;
+        let result = dup(target).and_then(|copy| {
+            saved.push((target, copy));
+            redirect(fd, target)
+        });
+        if let Err(e) = result {
+            restore_std(saved);
+            return Err(e)
+        }
+    }
+    Ok(saved)
+}
+
+/// Puts back the descriptors replaced by `replace_std`.
+pub fn restore_std(saved: Vec<(RawFd, OwnedFd)>) {
+    for (target, fd) in saved.into_iter().rev() {
+        let _ = redirect(&fd, target);
+    }
+}
+
+/// A change in the state of a child process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    /// Exited with a status, which for a process killed by a signal is
+    /// 128 plus the signal's number.
+    Exited(i32),
+    Stopped,
+    Continued
+}
+
+/// Waits for `pid` to exit and returns its exit status.
+pub fn wait_pid(pid: pid_t) -> io::Result<i32> {
+    loop {
+        if let Some(WaitStatus::Exited(status)) = waitpid(pid, 0)? {
+            return Ok(status)
+        }
+    }
+}
+
+/// Waits for `pid` to exit or be stopped.
+pub fn wait_pid_or_stop(pid: pid_t) -> io::Result<WaitStatus> {
+    loop {
+        match waitpid(pid, libc::WUNTRACED)? {
+            Some(WaitStatus::Continued) | None => {},
+            Some(status) => return Ok(status)
+        }
+    }
+}
+
+/// Returns how `pid` has changed since it was last waited on, without
+/// blocking. Stops and continues are reported as well as exits.
+pub fn try_wait_pid(pid: pid_t) -> io::Result<Option<WaitStatus>> {
+    waitpid(pid, libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED)
+}
+
+/// Calls waitpid, retrying if interrupted. Returns `None` if the process
+/// hasn't changed state.
+fn waitpid(pid: pid_t, options: libc::c_int) -> io::Result<Option<WaitStatus>> {
+    let mut status: libc::c_int = 0;
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, options) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e)
+                }
+            },
+            0 => return Ok(None),
+            _ if libc::WIFEXITED(status) => return Ok(Some(WaitStatus::Exited(libc::WEXITSTATUS(status)))),
+            _ if libc::WIFSTOPPED(status) => return Ok(Some(WaitStatus::Stopped)),
+            _ if libc::WIFCONTINUED(status) => return Ok(Some(WaitStatus::Continued)),
+            _ => return Ok(Some(WaitStatus::Exited(128 + libc::WTERMSIG(status))))
+        }
+    }
+}
+
+/// Sends `signal` to `pid`.
+pub fn kill(pid: pid_t, signal: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::kill(pid, signal) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Moves `pid` into the process group `pgid`, or into a new group of its
+/// own if `pgid` is 0.
+pub fn set_process_group(pid: pid_t, pgid: pid_t) -> io::Result<()> {
+    if unsafe { libc::setpgid(pid, pgid) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Makes `pgid` the foreground process group of the terminal on stdin,
+/// which is the group that receives keyboard signals and may read input.
+pub fn set_foreground(pgid: pid_t) -> io::Result<()> {
+    if unsafe { libc::tcsetpgrp(0, pgid) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// The signals an interactive shell ignores: those the terminal sends for
+/// Ctrl-C, Ctrl-\ and Ctrl-Z, which are meant for the foreground job, and
+/// those sent to background groups which use the terminal.
+const JOB_CONTROL_SIGNALS: [libc::c_int; 5] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTOU, libc::SIGTTIN];
+
+/// Puts the shell in a process group of its own, takes over the terminal
+/// and ignores the job control signals, so the shell can hand the terminal
+/// to jobs and take it back. Returns the shell's process group.
+pub fn take_terminal() -> io::Result<pid_t> {
+    for signal in JOB_CONTROL_SIGNALS {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+    // Fails harmlessly if the shell already leads a session
+    let _ = set_process_group(0, 0);
+    let pgid = unsafe { libc::getpgrp() };
+    set_foreground(pgid)?;
+    Ok(pgid)
+}
+
+/// The settings of the terminal on stdin.
+pub fn terminal_modes() -> io::Result<libc::termios> {
+    let mut modes = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(0, &mut modes) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(modes)
+}
+
+/// Restores settings of the terminal on stdin saved by `terminal_modes`.
+pub fn set_terminal_modes(modes: &libc::termios) -> io::Result<()> {
+    if unsafe { libc::tcsetattr(0, libc::TCSADRAIN, modes) } == -1 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Restores the default handling of the signals the shell ignores, for a
+/// child which is about to run a command. Only calls async-signal-safe
+/// functions, so it can be used between fork and exec.
+pub fn reset_signals() {
+    for signal in JOB_CONTROL_SIGNALS {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Exits a forked child without running the parent's exit handlers.
+pub fn exit_child(status: i32) -> ! {
+    unsafe { libc::_exit(status) }
+}
@@ -1,111 +1,239 @@
 use std::io::*;
-use std::process::{Command, Stdio, Child};
-use std::path::{self, PathBuf};
+use std::path::PathBuf;
 use std::env;
-use whoami;
-use crossterm::{ExecutableCommand, cursor, terminal};
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crossterm::{ExecutableCommand, terminal};
 
 
 mod tokenizer;
 mod parser;
-mod lib;
+mod interpreter;
+mod diagnostics;
+mod editor;
 
+use rush as lib;
+
+use parser::{ASTNode, Parser, SyntaxError};
+use interpreter::Interpreter;
+use interpreter::value::Value;
+use diagnostics::Renderer;
+use editor::{Editor, ReadLine};
+use editor::completion::Names;
+use editor::history::{self, Entry, History};
+
+/**
+ *  Runs a script when given one, and otherwise an interactive shell:
+ *
+ *  - `rush file [args...]` runs the file, which may start with a `#!` line
+ *  - `rush -c command [name [args...]]` runs the command string
+ *  - `rush` with stdin redirected from a file or pipe runs what it reads
+ */
 fn main() {
-    init_shell();
-    main_loop();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let status = match args.first().map(String::as_str) {
+        Some("-c") => match args.get(1) {
+            Some(command) => {
+                let name = args.get(2).map_or("rush", String::as_str);
+                run_script(name, command, args.get(3..).unwrap_or_default())
+            },
+            None => {
+                eprintln!("rush: -c: option requires an argument");
+                2
+            }
+        },
+        Some(path) => match fs::read_to_string(path) {
+            Ok(script) => run_script(path, &script, &args[1..]),
+            Err(e) => {
+                eprintln!("rush: {}: {}", path, e);
+                127
+            }
+        },
+        None if !stdin().is_terminal() => {
+            let mut script = String::new();
+            match stdin().read_to_string(&mut script) {
+                Ok(_) => run_script("rush", &script, &[]),
+                Err(e) => {
+                    eprintln!("rush: {}", e);
+                    1
+                }
+            }
+        },
+        None => {
+            init_shell();
+            main_loop()
+        }
+    };
+    let _ = stdout().flush();
+    std::process::exit(status);
 }
 
-fn main_loop() {
+/// Runs a whole script non-interactively, stopping at the first error, and
+/// returns the status the shell should exit with: 2 for a syntax error,
+/// and otherwise the status of the last command run.
+fn run_script(name: &str, script: &str, args: &[String]) -> i32 {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_arguments(name, args);
+    let program = match Parser::new().parse(script) {
+        Ok(program) => program,
+        Err(e) => {
+            eprint!("{}", Renderer::for_stderr(script).render("syntax error", &e));
+            return 2
+        }
+    };
+    interpreter.set_source(script);
+    if let Err(e) = interpreter.exec(&program) {
+        eprint!("{}", Renderer::for_stderr(script).render("error", &e));
+        // Errors which aren't from running a command still fail the script
+        return match interpreter.last_status() {
+            0 => 1,
+            status => status
+        }
+    }
+    interpreter.last_status()
+}
+
+/// Reads and runs commands until `exit` or the end of input, returning the
+/// status the shell should exit with.
+fn main_loop() -> i32 {
     let mut input: String = String::new();
+    let mut parser = Parser::new();
+    let mut editor = Editor::new();
+    let mut interpreter = Interpreter::new();
+    match history::default_path().map(|path| History::load(&path)) {
+        Some(Ok(history)) => *interpreter.history_mut() = history,
+        Some(Err(e)) => eprintln!("rush: can't read the history file: {}", e),
+        None => {}
+    }
+    if stdin().is_terminal() {
+        if let Err(e) = interpreter.enable_job_control() {
+            eprintln!("rush: job control is disabled: {}", e);
+        }
+    }
 
     loop {
         input.clear();
-        print!("{} in {}\nrush on {} > ", whoami::username(), env::current_dir().unwrap().to_str().unwrap(), whoami::hostname());
-        let _ = stdout().flush();
-
-        stdin().read_line(&mut input).unwrap();
-
-        let mut commands = input
-            .trim()
-            .split(" | ")
-            .peekable();
-        let mut previous_command = None;
-
-        while let Some(command) = commands.next() {
-            // Shadow input with trimmed input
-            let mut input = command
-                .trim()
-                .split_whitespace();
-            let command = input.next().unwrap();
-            let args = input;
-
-            match command {
-                "prompt" => {
-                    let prompt = args
-                        .peekable()
-                        .peek();
-                },
-                "cd" => {
-                    // default to '~/' as new directory if one was not provided
-                    // default to '/' if home dir doesn't exist
-                    let home = match home::home_dir() {
-                        Some(home_dir) => home_dir,
-                        None => PathBuf::from("/")
-                    };
-                    let new_dir = args
-                        .peekable()
-                        .peek()
-                        .map_or(home.to_str().unwrap_or("/"), |x| *x);
-                    let new_dir = path::Path::new(new_dir);
-                    if let Err(e) = env::set_current_dir(&new_dir) {
-                        eprintln!("{}", e);
-                    }
-                    set_title(format!("rush {}", "test"));
-                    previous_command = None;
-                },
-                "exit" => return,
-                command => {
-                    let stdin = previous_command
-                        .map_or(
-                            Stdio::inherit(),
-                            |output: Child| Stdio::from(output.stdout.unwrap())
-                        );
-
-                    let (stdout, stderr) = if commands.peek().is_some() {
-                        (Stdio::piped(), Stdio::piped())
-                    } else {
-                        (Stdio::inherit(), Stdio::inherit())
-                    };
-
-                    let process = Command::new(command)
-                        .args(args)
-                        .stdin(stdin)
-                        .stdout(stdout)
-                        .stderr(stderr)
-                        .spawn();
-
-                    match process {
-                        Ok(output) => {
-                            previous_command = Some(output)
-                        },
-                        Err(e) => {
-                            previous_command = None;
-                            eprintln!("Error: {}", e)
+        for job in interpreter.finished_jobs() {
+            eprintln!("{}", job);
+        }
+        editor.set_names(Names {
+            commands: interpreter.command_names(),
+            variables: interpreter.variable_names(),
+            path: interpreter.path()
+        });
+        let line = match editor.read_line(&prompt(&interpreter), interpreter.history()) {
+            Ok(ReadLine::Line(line)) => line,
+            // As if the line had been run and killed by SIGINT
+            Ok(ReadLine::Interrupted) => {
+                interpreter.set_status(128 + libc::SIGINT);
+                continue
+            },
+            Ok(ReadLine::EndOfInput) => return interpreter.last_status(),
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1
+            }
+        };
+        // History references are expanded before parsing, and the line
+        // they expand to is shown and kept in the history
+        match interpreter.history().expand(&line) {
+            Ok(expanded) if expanded != line => {
+                println!("{}", expanded);
+                input = expanded + "\n";
+            },
+            Ok(_) => input = line + "\n",
+            Err(reference) => {
+                eprintln!("rush: {}: event not found", reference);
+                continue
+            }
+        }
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let cwd = current_dir();
+        // Only running the line is timed, not typing any here-document
+        let mut duration = Duration::ZERO;
+        let status = match parse_input(&mut parser, &mut editor, interpreter.history(), &mut input) {
+            Some(program) => {
+                interpreter.set_source(&input);
+                let started = Instant::now();
+                let result = interpreter.exec(&program);
+                duration = started.elapsed();
+                match result {
+                    Ok(value) => {
+                        if !matches!(value, Value::None) {
+                            println!("{}", value);
+                        }
+                        interpreter.last_status()
+                    },
+                    Err(e) => {
+                        eprint!("{}", Renderer::for_stderr(&input).render("error", &e));
+                        match interpreter.last_status() {
+                            0 => 1,
+                            status => status
                         }
                     }
                 }
-            }
+            },
+            None => 2
+        };
+        let entry = Entry {
+            line: String::from(input.trim_end_matches('\n')),
+            time,
+            cwd,
+            duration: duration.as_millis() as u64,
+            status
+        };
+        if let Err(e) = interpreter.history_mut().add(entry) {
+            eprintln!("rush: can't write the history file: {}", e);
         }
+        if interpreter.exit_requested() {
+            return interpreter.last_status()
+        }
+        set_title(format!("rush {}", current_dir()));
+    }
+}
 
-        if let Some(mut final_command) = previous_command {
-            // wait until final command has finished, print if there's an error
-            if let Err(e) = final_command.wait() {
-                eprintln!("{}", e);
+/// The prompt, which shows the status of a failed command when the
+/// `RUSH_SHOW_STATUS` variable is set.
+fn prompt(interpreter: &Interpreter) -> String {
+    let show_status = interpreter
+        .variable("RUSH_SHOW_STATUS")
+        .is_some_and(|value| !value.to_string().is_empty());
+    let status = match interpreter.last_status() {
+        status if status != 0 && show_status => format!("[{}] ", status),
+        _ => String::new()
+    };
+    format!("{} in {}\nrush on {} {}> ", whoami::username(), current_dir(), whoami::hostname(), status)
+}
+
+/// Parses `input`, reading more lines into it while a here-document is
+/// still missing its delimiter. Syntax errors are reported on stderr.
+fn parse_input(parser: &mut Parser, editor: &mut Editor, history: &History, input: &mut String) -> Option<ASTNode> {
+    loop {
+        match parser.parse(input) {
+            Ok(program) => return Some(program),
+            Err(SyntaxError::UnterminatedHereDoc(..)) if read_continuation(editor, history, input) => {},
+            Err(e) => {
+                eprint!("{}", Renderer::for_stderr(input).render("syntax error", &e));
+                return None
             }
         }
     }
 }
 
+/// Prompts for another line of input and appends it to `input`. Returns
+/// false at the end of input.
+fn read_continuation(editor: &mut Editor, history: &History, input: &mut String) -> bool {
+    match editor.read_line("> ", history) {
+        Ok(ReadLine::Line(line)) => {
+            *input += &line;
+            input.push('\n');
+            true
+        },
+        _ => false
+    }
+}
+
 fn init_shell() {
     let home = match home::home_dir() {
         Some(home_dir) => home_dir,
@@ -124,14 +252,14 @@ fn init_shell() {
 }
 
 fn set_title(title: String) {
-    match lib::write_raw(format!("\x1b[2;{}\x07", title).as_str()) {
+    match lib::write_raw(format!("\x1b]2;{}\x07", title).as_str()) {
         Ok(_) => {},
         Err(e) => println!("ERROR: {}", e)
     }
 }
 
 fn current_dir() -> String {
-    String::from(env::current_dir().unwrap().clone().to_str().unwrap())
+    String::from(env::current_dir().unwrap().to_str().unwrap())
 }
 
 // Clear shell using escape sequence
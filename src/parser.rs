@@ -1,237 +1,993 @@
 use std::boxed::Box;
 use std::fmt;
+use std::rc::Rc;
 
-use crate::tokenizer::Tokenizer;
-use crate::tokenizer::token::Token;
-
-//pub use crate::parser::SyntaxError::*;
+use crate::tokenizer::{quoted_len, Tokenizer};
+use crate::tokenizer::token::{ExpansionOp, RedirectOp, Span, SpannedToken, Token};
 
+#[derive(Debug)]
 pub enum SyntaxError {
-    UnexpectedToken(String)
+    /// A token was found where one of the expected kinds should have been.
+    UnexpectedToken(Token, Vec<&'static str>, Span),
+    /// The input ended where one of the expected kinds should have been.
+    UnexpectedEOF(Vec<&'static str>, Span),
+    UnterminatedString(Span),
+    UnknownCharacter(char, Span),
+    InvalidNumber(String, Span),
+    /// A `${...}` expansion that isn't closed or doesn't name a variable.
+    BadSubstitution(Span),
+    /// A here-document whose delimiter line hasn't been found.
+    UnterminatedHereDoc(String, Span)
+}
+
+impl SyntaxError {
+    /// The source span of the offending text.
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnexpectedToken(_, _, span)
+            | SyntaxError::UnexpectedEOF(_, span)
+            | SyntaxError::UnterminatedString(span)
+            | SyntaxError::UnknownCharacter(_, span)
+            | SyntaxError::InvalidNumber(_, span)
+            | SyntaxError::BadSubstitution(span)
+            | SyntaxError::UnterminatedHereDoc(_, span) => *span
+        }
+    }
+
+    /// The error message, without the list of expected tokens.
+    pub fn description(&self) -> String {
+        match self {
+            // Punctuation says what it is already, but a word needs its kind
+            SyntaxError::UnexpectedToken(Token::Separator('\n'), _, _)
+                => String::from("Unexpected newline"),
+            SyntaxError::UnexpectedToken(found @ (
+                Token::Keyword(_)
+                | Token::Identifier(_)
+                | Token::NumberLiteral(_)
+                | Token::StringLiteral(_)
+                | Token::RawString(_)
+                | Token::BoolLiteral(_)
+                | Token::VarRef(_)
+                | Token::ParamExpansion(..)
+            ), _, _)
+                => format!("Unexpected {} `{}`", found.kind(), found),
+            SyntaxError::UnexpectedToken(found, _, _)
+                => format!("Unexpected `{}`", found),
+            SyntaxError::UnexpectedEOF(_, _)
+                => String::from("Unexpected end of input"),
+            SyntaxError::UnterminatedString(_)
+                => String::from("Unterminated string literal"),
+            SyntaxError::UnknownCharacter(c, _)
+                => format!("Unknown character '{}'", c.escape_default()),
+            SyntaxError::InvalidNumber(n, _)
+                => format!("Invalid number {}", n),
+            SyntaxError::BadSubstitution(_)
+                => String::from("Bad substitution"),
+            SyntaxError::UnterminatedHereDoc(delimiter, _)
+                => format!("Here-document is missing its `{}` delimiter line", delimiter),
+        }
+    }
+
+    /// The kinds of token that would have been accepted, if known.
+    pub fn expected(&self) -> &[&'static str] {
+        match self {
+            SyntaxError::UnexpectedToken(_, expected, _)
+            | SyntaxError::UnexpectedEOF(expected, _) => expected,
+            _ => &[]
+        }
+    }
 }
 
 impl fmt::Display for SyntaxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let tok = match self {
-            SyntaxError::UnexpectedToken(t) => t,
-        };
-        write!(f, "Unexpected token {}", tok)
+        write!(f, "{}", self.description())?;
+        match self.expected() {
+            [] => Ok(()),
+            expected => write!(f, ", expected {}", expected.join(" or "))
+        }
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ASTNodeType {
     StatementList(Vec<ASTNode>),
-    Statement(Box<ASTNode>),
     Expression(Box<ASTNode>),
+    BinaryExpression(String, Box<ASTNode>, Box<ASTNode>),
     VarDef(String, Box<ASTNode>),
     Identifier(String),
+    VarRef(String),
+    /// A `${...}` expansion with an operator, and the operator's words.
+    ParamExpansion(String, ExpansionOp, Vec<ASTNode>),
     StringLiteral(String),
+    /// Parts joined into one string, from an interpolated string or a word
+    /// made of several pieces.
+    Interpolation(Vec<ASTNode>),
+    /// A quoted part of a pattern, whose value only matches itself.
+    Quoted(Box<ASTNode>),
     NumberLiteral(f64),
     BoolLiteral(bool),
-    Command(String, Vec<ASTNode>),
-    Argslist,
-    None
+    /// A command's name, arguments and redirections.
+    Command(Box<ASTNode>, Vec<ASTNode>, Vec<ASTNode>),
+    /// A redirection and the file it refers to, which `N>&M` doesn't have.
+    Redirect(RedirectOp, Option<Box<ASTNode>>),
+    Pipeline(Vec<ASTNode>),
+    /// `&&` or `||` between two commands, the right one only run if the
+    /// left one succeeded or failed respectively.
+    AndOr(String, Box<ASTNode>, Box<ASTNode>),
+    /// A command or pipeline started without waiting for it to finish.
+    Background(Box<ASTNode>),
+    Block(Vec<ASTNode>),
+    If {
+        cond: Box<ASTNode>,
+        then_block: Box<ASTNode>,
+        else_branch: Option<Box<ASTNode>>
+    },
+    FnDef {
+        name: String,
+        params: Vec<String>,
+        body: Rc<ASTNode>
+    },
+    Return(Option<Box<ASTNode>>)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct ASTNode {
-    node_type: ASTNodeType,
+    pub node_type: ASTNodeType,
+    pub span: Span
+}
+
+impl PartialEq for ASTNode {
+    /// Nodes compare by structure only, regardless of where they appear in
+    /// the source.
+    fn eq(&self, other: &Self) -> bool {
+        self.node_type == other.node_type
+    }
 }
 
 pub struct Parser {
-    lookahead: Token,
+    lookahead: SpannedToken,
+    previous_span: Span,
     tokenizer: Tokenizer
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     pub fn new() -> Self {
         Parser {
-            lookahead: Token::Empty,
+            lookahead: SpannedToken {
+                token: Token::Empty,
+                span: Span::default()
+            },
+            previous_span: Span::default(),
             tokenizer: Tokenizer::new()
         }
     }
 
-    pub fn parse(&mut self, input: &str) -> ASTNode {
-        self.tokenizer.init(input);
-        self.update_lookahead();
-        return ASTNode {
-            node_type: ASTNodeType::StatementList(self.statement_list())
+    pub fn parse(&mut self, input: &str) -> Result<ASTNode, SyntaxError> {
+        self.tokenizer = Tokenizer::from(input);
+        self.previous_span = Span::default();
+        self.update_lookahead()?;
+        let start = self.lookahead.span;
+        let statements = self.statement_list()?;
+        Ok(self.node(ASTNodeType::StatementList(statements), start))
+    }
+
+    fn update_lookahead(&mut self) -> Result<(), SyntaxError> {
+        self.previous_span = self.lookahead.span;
+        self.lookahead = self.tokenizer.get_next_token()?;
+        Ok(())
+    }
+
+    /// Reads the next token in word mode, for command names and arguments.
+    fn update_lookahead_word(&mut self) -> Result<(), SyntaxError> {
+        self.previous_span = self.lookahead.span;
+        self.lookahead = self.tokenizer.get_next_word()?;
+        Ok(())
+    }
+
+    /// Runs `read` on the tokenizer and then puts it back where it was, to
+    /// look past the lookahead without consuming anything.
+    fn look_ahead<T>(&mut self, read: impl FnOnce(&mut Tokenizer) -> Result<T, SyntaxError>) -> Result<T, SyntaxError> {
+        let position = self.tokenizer.position();
+        let result = read(&mut self.tokenizer);
+        self.tokenizer.restore(position);
+        result
+    }
+
+    /// Reads the token after the lookahead, without consuming anything.
+    fn peek(&mut self) -> Result<Token, SyntaxError> {
+        self.look_ahead(|tokenizer| Ok(tokenizer.get_next_token()?.token))
+    }
+
+    /// Whether the lookahead starts a command rather than an expression.
+    /// `true` and `false` are booleans unless a pipeline operator follows,
+    /// as in `false || echo fallback`. Values are commands when followed by
+    /// more words, as in `$editor notes.txt`.
+    fn at_command(&mut self) -> Result<bool, SyntaxError> {
+        Ok(match self.lookahead.token {
+            Token::Identifier(_) | Token::BinaryOp(_) => true,
+            Token::BoolLiteral(_) => matches!(self.peek()?, Token::LogicalOp(_) | Token::Pipe | Token::PipeAll),
+            Token::VarRef(_) | Token::ParamExpansion(..) | Token::StringLiteral(_) | Token::RawString(_)
+                => self.words_follow()?,
+            _ => false
+        })
+    }
+
+    /// Whether the lookahead is followed by more words on its line, rather
+    /// than by the rest of an expression. An operator only starts a word
+    /// when it's apart from the lookahead but touches the text after it, as
+    /// in `$ls -la`, or when no operand comes after it, as in `$cd /`.
+    fn words_follow(&mut self) -> Result<bool, SyntaxError> {
+        let lookahead_end = self.lookahead.span.end;
+        self.look_ahead(|tokenizer| {
+            let next = tokenizer.get_next_token()?;
+            Ok(match next.token {
+                Token::BinaryOp(_) => {
+                    let after = tokenizer.get_next_token()?;
+                    next.span.start > lookahead_end
+                        && (after.span.start == next.span.end || !matches!(after.token,
+                            Token::NumberLiteral(_)
+                            | Token::StringLiteral(_)
+                            | Token::RawString(_)
+                            | Token::BoolLiteral(_)
+                            | Token::VarRef(_)
+                            | Token::ParamExpansion(..)
+                            | Token::Identifier(_)
+                            | Token::OpenParen))
+                },
+                Token::Separator(_)
+                | Token::Empty
+                | Token::OpenBrace
+                | Token::CloseBrace
+                | Token::CloseParen
+                | Token::Comma
+                | Token::AssignmentOp(_)
+                    => false,
+                _ => true
+            })
+        })
+    }
+
+    /// Builds a node spanning from `start` to the end of the last consumed token.
+    fn node(&self, node_type: ASTNodeType, start: Span) -> ASTNode {
+        ASTNode {
+            node_type,
+            span: start.to(self.previous_span)
         }
     }
 
-    fn update_lookahead(&mut self) {
-        self.lookahead = self.tokenizer.get_next_token(false);
+    /// Builds the error for a lookahead which isn't any of the `expected` kinds.
+    fn unexpected(&self, expected: Vec<&'static str>) -> SyntaxError {
+        match self.lookahead.token {
+            Token::Empty => SyntaxError::UnexpectedEOF(expected, self.lookahead.span),
+            ref token => SyntaxError::UnexpectedToken(token.clone(), expected, self.lookahead.span)
+        }
     }
 
-    fn expect(&mut self, tok_type: Token) -> Token {
-        let token = self.lookahead.clone();
+    fn expect(&mut self, tok_type: Token) -> Result<Token, SyntaxError> {
+        let token = self.lookahead.token.clone();
 
         if token == tok_type {
-            self.update_lookahead();
-            return token
+            self.update_lookahead()?;
+            return Ok(token)
         }
-        panic!("{}", SyntaxError::UnexpectedToken(token.to_string()));
+        Err(self.unexpected(vec![tok_type.kind()]))
+    }
+
+    /// Skips any `;` and newline separators between statements.
+    fn skip_separators(&mut self) -> Result<(), SyntaxError> {
+        while let Token::Separator(_) = self.lookahead.token {
+            self.update_lookahead()?;
+        }
+        Ok(())
+    }
+
+    /// Skips newlines where a statement carries on to the next line, such as
+    /// after an operator.
+    fn skip_newlines(&mut self) -> Result<(), SyntaxError> {
+        while matches!(self.lookahead.token, Token::Separator('\n')) {
+            self.update_lookahead()?;
+        }
+        Ok(())
     }
 
     /**
      *  StatementList
      *  : Statement
-     *  | Statement LOGICAL_OP StatementList
+     *  | Statement StatementList
+     *  | Statement SEPARATOR StatementList
+     *
+     *  Statements may be separated by any number of `;` and newlines.
      */
-    pub fn statement_list(&mut self) -> Vec<ASTNode> {
+    pub fn statement_list(&mut self) -> Result<Vec<ASTNode>, SyntaxError> {
         let mut statements = Vec::<ASTNode>::new();
 
-        while self.lookahead != Token::Empty {
-            println!("{}", self.lookahead.clone());
-            statements.push(self.statement());
+        self.skip_separators()?;
+        while self.lookahead.token != Token::Empty {
+            statements.push(self.statement()?);
+            self.skip_separators()?;
         }
-        statements
+        Ok(statements)
     }
 
     /**
      *  Statement
      *  : IfStatement
+     *  | FnDef
+     *  | ReturnStatement
+     *  | VarDef
      *  | Expression
+     *  | PipelineStatement
      */
-    pub fn statement(&mut self) -> ASTNode {
-        let look = self.lookahead.clone();
+    pub fn statement(&mut self) -> Result<ASTNode, SyntaxError> {
+        if self.at_command()? {
+            return self.pipeline_statement()
+        }
+        let look = self.lookahead.token.clone();
         match look {
-            Token::Keyword(s) if s == String::from("if")
+            Token::Keyword(s) if s == "if"
                 => self.if_statement(),
-            Token::Keyword(s) if s == String::from("fn")
+            Token::Keyword(s) if s == "fn"
                 => self.fn_def(),
+            Token::Keyword(s) if s == "return"
+                => self.return_statement(),
             Token::Let
                 => self.var_def(),
-            Token::Identifier(s)
-                => self.command_expression(),
-            _ => panic!(),
+            // `true` and `false` statements are commands, to set the status
+            Token::BoolLiteral(_) if !matches!(self.peek()?, Token::BinaryOp(_))
+                => self.pipeline_statement(),
+            Token::NumberLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::RawString(_)
+            | Token::BoolLiteral(_)
+            | Token::VarRef(_)
+            | Token::ParamExpansion(..)
+            | Token::OpenParen
+                => self.expression(),
+            _ => Err(self.unexpected(vec!["command", "expression", "`let`", "`if`", "`fn`"])),
+        }
+    }
+
+    /**
+     *  PipelineStatement
+     *  : AndOrList
+     *  | AndOrList `&`
+     */
+    pub fn pipeline_statement(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let list = self.and_or_list()?;
+        if matches!(&self.lookahead.token, Token::LogicalOp(op) if op == "&") {
+            self.update_lookahead()?;
+            return Ok(self.node(ASTNodeType::Background(Box::from(list)), start))
         }
+        Ok(list)
     }
 
-    pub fn command_expression(&mut self) -> ASTNode {
-        let command = self.expect(Token::Identifier(String::new()));
-        let mut args = Vec::<ASTNode>::new();
+    /**
+     *  AndOrList
+     *  : Pipeline
+     *  | AndOrList `&&` Pipeline
+     *  | AndOrList `||` Pipeline
+     */
+    pub fn and_or_list(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let mut left = self.pipeline()?;
+        while let Token::LogicalOp(op) = self.lookahead.token.clone() {
+            if op != "&&" && op != "||" {
+                break;
+            }
+            self.update_lookahead()?;
+            self.skip_newlines()?;
+            let right = self.pipeline()?;
+            left = self.node(ASTNodeType::AndOr(op, Box::from(left), Box::from(right)), start);
+        }
+        Ok(left)
+    }
+
+    /**
+     *  Pipeline
+     *  : CommandExpression
+     *  | CommandExpression PIPE Pipeline
+     *  | CommandExpression PIPE_ALL Pipeline
+     *
+     *  `|&` pipes stderr along with stdout, as if the command ended with
+     *  `2>&1`.
+     */
+    pub fn pipeline(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let mut commands = vec![self.command_expression()?];
+        while let Token::Pipe | Token::PipeAll = self.lookahead.token {
+            if self.lookahead.token == Token::PipeAll {
+                let merge = ASTNode {
+                    node_type: ASTNodeType::Redirect(RedirectOp::Duplicate(2, 1), None),
+                    span: self.lookahead.span
+                };
+                if let Some(ASTNode { node_type: ASTNodeType::Command(_, _, redirects), .. }) = commands.last_mut() {
+                    redirects.push(merge);
+                }
+            }
+            self.update_lookahead()?;
+            self.skip_newlines()?;
+            commands.push(self.command_expression()?);
+        }
+        if commands.len() == 1 {
+            return Ok(commands.pop().unwrap())
+        }
+        Ok(self.node(ASTNodeType::Pipeline(commands), start))
+    }
+
+    /**
+     *  CommandExpression
+     *  : Word
+     *  | Word Argslist
+     *
+     *  Redirections can appear anywhere among the words.
+     */
+    pub fn command_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        // The lookahead was read as a regular token, so read it again as a word
+        self.tokenizer.rewind();
+        self.update_lookahead_word()?;
+        let start = self.lookahead.span;
+
+        let mut words = Vec::<ASTNode>::new();
+        let mut redirects = Vec::<ASTNode>::new();
         loop {
-            match self.lookahead.clone() {
-                Token::Identifier(s) => {
-                    let ident_str = match self.expect(Token::Identifier(String::new())) {
-                        Token::Identifier(s) => s,
-                        _ => panic!()
-                    };
-                    args.push(
-                        self.identifier(
-                            ident_str
-                        )
-                    )
+            if let Token::Redirect(_) | Token::HereDoc(..) = self.lookahead.token {
+                redirects.push(self.redirect()?);
+            } else if let Some(word) = self.word()? {
+                words.push(word);
+            } else {
+                break;
+            }
+        }
+        if words.is_empty() {
+            return Err(self.unexpected(vec!["command"]))
+        }
+        let command = words.remove(0);
+        Ok(self.node(ASTNodeType::Command(Box::from(command), words, redirects), start))
+    }
+
+    /**
+     *  Redirect
+     *  : REDIRECT_OP Word
+     *  | DUPLICATE_OP
+     *  | HEREDOC
+     */
+    pub fn redirect(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let op = match self.lookahead.token.clone() {
+            Token::Redirect(op) => op,
+            Token::HereDoc(fd, lines, expand) => {
+                self.update_lookahead_word()?;
+                let body = self.here_doc_body(lines, expand, start)?;
+                return Ok(self.node(ASTNodeType::Redirect(RedirectOp::HereDoc(fd), Some(Box::from(body))), start))
+            },
+            _ => return Err(self.unexpected(vec!["redirection"]))
+        };
+        self.update_lookahead_word()?;
+        let target = match op {
+            RedirectOp::Duplicate(..) => None,
+            _ => match self.word()? {
+                Some(target) => Some(Box::from(target)),
+                None => return Err(self.unexpected(vec!["file name"]))
+            }
+        };
+        Ok(self.node(ASTNodeType::Redirect(op, target), start))
+    }
+
+    /**
+     *  Word
+     *  : WORD_PIECE
+     *  | WORD_PIECE Word
+     *
+     *  The pieces of a word are only joined when there's no whitespace
+     *  between them.
+     */
+    pub fn word(&mut self) -> Result<Option<ASTNode>, SyntaxError> {
+        let start = self.lookahead.span;
+        let mut pieces = Vec::<ASTNode>::new();
+        loop {
+            if !pieces.is_empty() && self.lookahead.span.start != self.previous_span.end {
+                break;
+            }
+            let piece = match self.lookahead.token.clone() {
+                Token::Identifier(s) | Token::RawString(s) => {
+                    self.update_lookahead_word()?;
+                    self.string_literal(s)
+                },
+                Token::StringLiteral(s) => {
+                    let span = self.lookahead.span;
+                    self.update_lookahead_word()?;
+                    self.interpolate(&s, span.start + 1, span)?
+                },
+                var_ref @ (Token::VarRef(_) | Token::ParamExpansion(..)) => {
+                    let span = self.lookahead.span;
+                    self.update_lookahead_word()?;
+                    self.var_ref(var_ref, span)?
                 },
-                Token::NumberLiteral(n) => args.push(self.number_literal(n)),
-                Token::StringLiteral(s) => args.push(self.string_literal(s)),
-                Token::BoolLiteral(b) => args.push(self.bool_literal(b)),
                 _ => break
+            };
+            pieces.push(piece);
+        }
+        match pieces.len() {
+            0 => Ok(None),
+            1 => Ok(pieces.pop()),
+            _ => Ok(Some(self.node(ASTNodeType::Interpolation(pieces), start)))
+        }
+    }
+
+    /**
+     *  Splits the raw text of a double-quoted string, or of a word in a
+     *  parameter expansion, into literal text and variable references.
+     *  `offset` is the position of the text in the source and `span` covers
+     *  the whole node. Backslashes escape `$`, `"`, `\` and backticks, and
+     *  are kept before anything else.
+     */
+    pub fn interpolate(&self, raw: &str, offset: usize, span: Span) -> Result<ASTNode, SyntaxError> {
+        let mut parts = Vec::<ASTNode>::new();
+        let mut text = String::new();
+        let mut text_start = 0;
+        let mut chars = raw.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => match chars.peek() {
+                    Some(&(_, escaped @ ('$' | '"' | '\\' | '`'))) => {
+                        text.push(escaped);
+                        chars.next();
+                    },
+                    _ => text.push('\\')
+                },
+                '$' => match self.tokenizer.var_ref_at(offset + index)? {
+                    Some((var_ref, len)) => {
+                        if !text.is_empty() {
+                            parts.push(ASTNode {
+                                node_type: ASTNodeType::StringLiteral(std::mem::take(&mut text)),
+                                span: self.tokenizer.span(offset + text_start, offset + index)
+                            });
+                        }
+                        parts.push(self.var_ref(var_ref, self.tokenizer.span(offset + index, offset + index + len))?);
+                        while chars.next_if(|(next, _)| *next < index + len).is_some() {}
+                        text_start = index + len;
+                    },
+                    None => text.push('$')
+                },
+                c => text.push(c)
             }
         }
-        ASTNode {
-            node_type: ASTNodeType::None
+
+        if parts.is_empty() {
+            return Ok(ASTNode {
+                node_type: ASTNodeType::StringLiteral(text),
+                span
+            })
         }
+        if !text.is_empty() {
+            parts.push(ASTNode {
+                node_type: ASTNodeType::StringLiteral(text),
+                span: self.tokenizer.span(offset + text_start, offset + raw.len())
+            });
+        }
+        Ok(ASTNode {
+            node_type: ASTNodeType::Interpolation(parts),
+            span
+        })
     }
 
-    pub fn identifier(&mut self, s: String) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::Identifier(s)
+    /**
+     *  Splits the raw text of a word in a parameter expansion into its
+     *  pieces, removing quotes and backslashes as for a command's argument.
+     *  In a `pattern`, quoted pieces only match themselves, and backslashes
+     *  are kept to escape the character after them.
+     */
+    pub fn expansion_word(&self, raw: &str, offset: usize, span: Span, pattern: bool) -> Result<ASTNode, SyntaxError> {
+        let mut parts = Vec::<ASTNode>::new();
+        let mut text = String::new();
+        let mut text_start = 0;
+        let mut chars = raw.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            let (part, len) = match c {
+                '\\' => {
+                    if pattern {
+                        text.push('\\');
+                    }
+                    if let Some((_, escaped)) = chars.next() {
+                        text.push(escaped);
+                    }
+                    continue
+                },
+                quote @ ('"' | '\'') => {
+                    let Some(len) = quoted_len(&raw[index + 1..], quote) else {
+                        text.push(quote);
+                        continue
+                    };
+                    let quoted = &raw[index + 1..index + 1 + len];
+                    let part_span = self.tokenizer.span(offset + index, offset + index + len + 2);
+                    let part = if quote == '"' {
+                        self.interpolate(quoted, offset + index + 1, part_span)?
+                    } else {
+                        ASTNode {
+                            node_type: ASTNodeType::StringLiteral(String::from(quoted)),
+                            span: part_span
+                        }
+                    };
+                    if pattern {
+                        (ASTNode { node_type: ASTNodeType::Quoted(Box::from(part)), span: part_span }, len + 2)
+                    } else {
+                        (part, len + 2)
+                    }
+                },
+                '$' => match self.tokenizer.var_ref_at(offset + index)? {
+                    Some((var_ref, len)) => (self.var_ref(var_ref, self.tokenizer.span(offset + index, offset + index + len))?, len),
+                    None => {
+                        text.push('$');
+                        continue
+                    }
+                },
+                c => {
+                    text.push(c);
+                    continue
+                }
+            };
+            if !text.is_empty() {
+                parts.push(ASTNode {
+                    node_type: ASTNodeType::StringLiteral(std::mem::take(&mut text)),
+                    span: self.tokenizer.span(offset + text_start, offset + index)
+                });
+            }
+            parts.push(part);
+            while chars.next_if(|(next, _)| *next < index + len).is_some() {}
+            text_start = index + len;
         }
+
+        if !text.is_empty() || parts.is_empty() {
+            parts.push(ASTNode {
+                node_type: ASTNodeType::StringLiteral(text),
+                span: self.tokenizer.span(offset + text_start, offset + raw.len())
+            });
+        }
+        if parts.len() == 1 && matches!(parts[0].node_type, ASTNodeType::StringLiteral(_)) {
+            return Ok(ASTNode {
+                node_type: parts.remove(0).node_type,
+                span
+            })
+        }
+        Ok(ASTNode {
+            node_type: ASTNodeType::Interpolation(parts),
+            span
+        })
     }
 
-    pub fn number_literal(&mut self, n: f64) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::NumberLiteral(n)
+    /// Joins the lines of a here-document, interpolating each of them if
+    /// `expand` is set. `span` is the span of the here-document operator.
+    pub fn here_doc_body(&self, lines: Vec<(String, usize)>, expand: bool, span: Span) -> Result<ASTNode, SyntaxError> {
+        let mut parts = Vec::<ASTNode>::new();
+        for (line, start) in lines {
+            let line_span = self.tokenizer.span(start, start + line.len());
+            parts.push(if expand {
+                self.interpolate(&line, start, line_span)?
+            } else {
+                ASTNode {
+                    node_type: ASTNodeType::StringLiteral(line),
+                    span: line_span
+                }
+            });
         }
+        Ok(ASTNode {
+            node_type: ASTNodeType::Interpolation(parts),
+            span
+        })
+    }
+
+    /*
+     *  Literal and identifier nodes span the token that was just consumed.
+     */
+
+    pub fn identifier(&mut self, s: String) -> ASTNode {
+        self.node(ASTNodeType::Identifier(s), self.previous_span)
+    }
+
+    /// Turns a VarRef or ParamExpansion token covering `span` into a node,
+    /// interpolating the words of an expansion.
+    pub fn var_ref(&self, token: Token, span: Span) -> Result<ASTNode, SyntaxError> {
+        let node_type = match token {
+            Token::ParamExpansion(name, op, words) => {
+                // Only the first word of these is a pattern, not a replacement
+                let pattern = matches!(op, ExpansionOp::RemovePrefix(_) | ExpansionOp::RemoveSuffix(_) | ExpansionOp::Replace(_));
+                let words = words
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, (word, start))| {
+                        self.expansion_word(&word, start, self.tokenizer.span(start, start + word.len()), pattern && index == 0)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                ASTNodeType::ParamExpansion(name, op, words)
+            },
+            Token::VarRef(name) => ASTNodeType::VarRef(name),
+            _ => unreachable!("only called with variable tokens")
+        };
+        Ok(ASTNode {
+            node_type,
+            span
+        })
+    }
+
+    pub fn number_literal(&mut self, n: f64) -> ASTNode {
+        self.node(ASTNodeType::NumberLiteral(n), self.previous_span)
     }
 
     pub fn string_literal(&mut self, s: String) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::StringLiteral(s)
-        }
+        self.node(ASTNodeType::StringLiteral(s), self.previous_span)
     }
 
     pub fn bool_literal(&mut self, b: bool) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::BoolLiteral(b)
-        }
+        self.node(ASTNodeType::BoolLiteral(b), self.previous_span)
     }
 
     /**
      *  Expression
-     *  : BinaryExpression
-     *  | Command Argslist
+     *  : ComparisonExpression
+     */
+    pub fn expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        self.comparison_expression()
+    }
+
+    /**
+     *  ComparisonExpression
+     *  : AdditiveExpression
+     *  | AdditiveExpression COMPARISON_OP AdditiveExpression
      */
-    pub fn expression(&mut self) -> ASTNode {
-        let look = self.lookahead.clone();
+    pub fn comparison_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let left = self.additive_expression()?;
+        if let Token::BinaryOp(op) = self.lookahead.token.clone() {
+            if matches!(op.as_str(), "==" | "!=" | "<" | ">" | "<=" | ">=") {
+                self.update_lookahead()?;
+                self.skip_newlines()?;
+                let right = self.additive_expression()?;
+                return Ok(self.node(ASTNodeType::BinaryExpression(op, Box::from(left), Box::from(right)), start))
+            }
+        }
+        Ok(left)
+    }
+
+    /**
+     *  AdditiveExpression
+     *  : MultiplicativeExpression
+     *  | AdditiveExpression ADDITIVE_OP MultiplicativeExpression
+     */
+    pub fn additive_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let mut left = self.multiplicative_expression()?;
+        while let Token::BinaryOp(op) = self.lookahead.token.clone() {
+            if op != "+" && op != "-" {
+                break;
+            }
+            self.update_lookahead()?;
+            self.skip_newlines()?;
+            let right = self.multiplicative_expression()?;
+            left = self.node(ASTNodeType::BinaryExpression(op, Box::from(left), Box::from(right)), start);
+        }
+        Ok(left)
+    }
+
+    /**
+     *  MultiplicativeExpression
+     *  : PrimaryExpression
+     *  | MultiplicativeExpression MULTIPLICATIVE_OP PrimaryExpression
+     */
+    pub fn multiplicative_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        let mut left = self.primary_expression()?;
+        while let Token::BinaryOp(op) = self.lookahead.token.clone() {
+            if op != "*" && op != "/" {
+                break;
+            }
+            self.update_lookahead()?;
+            self.skip_newlines()?;
+            let right = self.primary_expression()?;
+            left = self.node(ASTNodeType::BinaryExpression(op, Box::from(left), Box::from(right)), start);
+        }
+        Ok(left)
+    }
+
+    /**
+     *  PrimaryExpression
+     *  : Literal
+     *  | Identifier
+     *  | VarRef
+     *  | ParenthesizedExpression
+     */
+    pub fn primary_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        let look = self.lookahead.token.clone();
         match look {
-            Token::StringLiteral(string) => match self.expect(Token::StringLiteral(string)) {
-                Token::StringLiteral(s) => ASTNode {
-                    node_type: ASTNodeType::StringLiteral(s)
-                },
-                _ => panic!()
+            Token::StringLiteral(s) => {
+                let span = self.lookahead.span;
+                self.update_lookahead()?;
+                self.interpolate(&s, span.start + 1, span)
             },
-            Token::NumberLiteral(number) => match self.expect(Token::NumberLiteral(number)) {
-                Token::NumberLiteral(n) => ASTNode {
-                    node_type: ASTNodeType::NumberLiteral(n)
-                },
-                _ => panic!()
+            Token::RawString(s) => {
+                self.update_lookahead()?;
+                Ok(self.string_literal(s))
             },
-            Token::BoolLiteral(boolean) => match self.expect(Token::BoolLiteral(boolean)) {
-                Token::BoolLiteral(b) => ASTNode {
-                    node_type: ASTNodeType::BoolLiteral(b)
-                },
-                _ => panic!()
+            var_ref @ (Token::VarRef(_) | Token::ParamExpansion(..)) => {
+                let span = self.lookahead.span;
+                self.update_lookahead()?;
+                self.var_ref(var_ref, span)
             },
-            Token::OpenParen => match self.expect(Token::OpenParen) {
-                Token::OpenParen => self.parenthesized_expression(),
-                _ => panic!()
-            }
-            _ => panic!()
+            Token::NumberLiteral(n) => {
+                self.update_lookahead()?;
+                Ok(self.number_literal(n))
+            },
+            Token::BoolLiteral(b) => {
+                self.update_lookahead()?;
+                Ok(self.bool_literal(b))
+            },
+            Token::Identifier(s) => {
+                self.update_lookahead()?;
+                Ok(self.identifier(s))
+            },
+            Token::OpenParen => self.parenthesized_expression(),
+            _ => Err(self.unexpected(vec!["expression"]))
         }
     }
 
-    pub fn parenthesized_expression(&mut self) -> ASTNode {
-        self.expect(Token::OpenParen);
-        let node = ASTNode {
-            node_type: ASTNodeType::Expression(Box::from(self.expression()))
-        };
-        self.expect(Token::CloseParen);
-        node
+    pub fn parenthesized_expression(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        self.expect(Token::OpenParen)?;
+        self.skip_newlines()?;
+        let inner = self.expression()?;
+        self.skip_newlines()?;
+        self.expect(Token::CloseParen)?;
+        Ok(self.node(ASTNodeType::Expression(Box::from(inner)), start))
     }
 
-    pub fn var_def(&mut self) -> ASTNode {
-        self.expect(Token::Let);
-        let name: String = match self.expect(Token::Identifier(String::new())) {
+    pub fn var_def(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        self.expect(Token::Let)?;
+        let name: String = match self.expect(Token::Identifier(String::new()))? {
             Token::Identifier(name) => name,
-            _ => { panic!(); }
+            _ => unreachable!()
         };
-        self.expect(Token::AssignmentOp(String::from("=")));
-        let value: ASTNode = self.expression();
-        ASTNode {
-            node_type: ASTNodeType::VarDef(name, Box::from(value))
+        self.expect(Token::AssignmentOp(String::from("=")))?;
+        self.skip_newlines()?;
+        let value: ASTNode = self.expression()?;
+        Ok(self.node(ASTNodeType::VarDef(name, Box::from(value)), start))
+    }
+
+    /**
+     *  Block
+     *  : OPEN_BRACE CLOSE_BRACE
+     *  | OPEN_BRACE StatementList CLOSE_BRACE
+     */
+    pub fn block(&mut self) -> Result<ASTNode, SyntaxError> {
+        self.skip_newlines()?;
+        let start = self.lookahead.span;
+        self.expect(Token::OpenBrace)?;
+        let mut statements = Vec::<ASTNode>::new();
+        self.skip_separators()?;
+        while self.lookahead.token != Token::CloseBrace && self.lookahead.token != Token::Empty {
+            statements.push(self.statement()?);
+            self.skip_separators()?;
         }
+        self.expect(Token::CloseBrace)?;
+        Ok(self.node(ASTNodeType::Block(statements), start))
     }
 
-    pub fn command(&mut self, s: String) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::Command(String::from(""), Vec::new())
+    /**
+     *  IfStatement
+     *  : IF Condition Block
+     *  | IF Condition Block ELSE Block
+     *  | IF Condition Block ELSE IfStatement
+     */
+    pub fn if_statement(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        self.expect(Token::Keyword(String::from("if")))?;
+        let cond = self.condition()?;
+        let then_block = self.block()?;
+        // `else` may start the next line
+        if matches!(self.lookahead.token, Token::Separator('\n')) && matches!(self.peek()?, Token::Keyword(ref s) if s == "else") {
+            self.update_lookahead()?;
         }
+
+        let else_branch = match self.lookahead.token.clone() {
+            Token::Keyword(s) if s == "else" => {
+                self.update_lookahead()?;
+                self.skip_newlines()?;
+                match self.lookahead.token.clone() {
+                    Token::Keyword(s) if s == "if" => Some(Box::from(self.if_statement()?)),
+                    Token::OpenBrace => Some(Box::from(self.block()?)),
+                    _ => return Err(self.unexpected(vec!["`{`", "`if`"]))
+                }
+            },
+            _ => None
+        };
+
+        Ok(self.node(ASTNodeType::If {
+            cond: Box::from(cond),
+            then_block: Box::from(then_block),
+            else_branch
+        }, start))
     }
 
-    pub fn if_statement(&mut self) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::Argslist
+    /**
+     *  Condition
+     *  : Expression
+     *  | AndOrList
+     *
+     *  Conditions starting with a word are commands, whose exit status
+     *  decides the branch.
+     */
+    pub fn condition(&mut self) -> Result<ASTNode, SyntaxError> {
+        if self.at_command()? {
+            return self.and_or_list()
         }
+        self.expression()
     }
 
-    pub fn fn_def(&mut self) -> ASTNode {
-        ASTNode {
-            node_type: ASTNodeType::Argslist
+    /**
+     *  FnDef
+     *  : FN IDENTIFIER OPEN_PAREN CLOSE_PAREN Block
+     *  | FN IDENTIFIER OPEN_PAREN ParamList CLOSE_PAREN Block
+     *
+     *  ParamList
+     *  : IDENTIFIER
+     *  | IDENTIFIER COMMA ParamList
+     */
+    pub fn fn_def(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        self.expect(Token::Keyword(String::from("fn")))?;
+        let name = match self.expect(Token::Identifier(String::new()))? {
+            Token::Identifier(name) => name,
+            _ => unreachable!()
+        };
+
+        self.expect(Token::OpenParen)?;
+        self.skip_newlines()?;
+        let mut params = Vec::<String>::new();
+        while let Token::Identifier(param) = self.lookahead.token.clone() {
+            self.update_lookahead()?;
+            params.push(param);
+            self.skip_newlines()?;
+            if self.lookahead.token != Token::Comma {
+                break;
+            }
+            self.update_lookahead()?;
+            self.skip_newlines()?;
+        }
+        if self.lookahead.token != Token::CloseParen {
+            let expected = if params.is_empty() { vec!["identifier", "`)`"] } else { vec!["`,`", "`)`"] };
+            return Err(self.unexpected(expected))
         }
+        self.update_lookahead()?;
+
+        let body = self.block()?;
+        Ok(self.node(ASTNodeType::FnDef {
+            name,
+            params,
+            body: Rc::from(body)
+        }, start))
+    }
+
+    /**
+     *  ReturnStatement
+     *  : RETURN
+     *  | RETURN Expression
+     */
+    pub fn return_statement(&mut self) -> Result<ASTNode, SyntaxError> {
+        let start = self.lookahead.span;
+        self.expect(Token::Keyword(String::from("return")))?;
+        let value = match self.lookahead.token {
+            Token::CloseBrace | Token::Empty | Token::Separator(_) => None,
+            _ => Some(Box::from(self.expression()?))
+        };
+        Ok(self.node(ASTNodeType::Return(value), start))
     }
 }
 
@@ -248,20 +1004,304 @@ mod tests {
     #[test]
     fn parser_test_2() {
         let mut p = Parser::new();
-        let parsed = p.parse("let xawd = 10.0");
+        let parsed = p.parse("let xawd = 10.0").unwrap();
         println!("{:?}", parsed);
-        let mut control = Vec::new();
-        control.push(
+        let control = vec![
             ASTNode{
                 node_type: ASTNodeType::VarDef(String::from("xawd"), Box::from(
                     ASTNode {
-                        node_type: ASTNodeType::NumberLiteral(10.0)
+                        node_type: ASTNodeType::NumberLiteral(10.0),
+                        span: Span::default()
                     }
                 )),
+                span: Span::default()
             }
-        );
+        ];
         assert_eq!(parsed, ASTNode {
-            node_type: ASTNodeType::StatementList(control)
+            node_type: ASTNodeType::StatementList(control),
+            span: Span::default()
         });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parser_test_3() {
+        let mut p = Parser::new();
+        let parsed = p.parse("let x = 1\nls -a /tmp | wc -l").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements[0].span, Span { start: 0, end: 9, line: 1, column: 1 });
+        assert_eq!(statements[1].span, Span { start: 10, end: 28, line: 2, column: 1 });
+        match &statements[1].node_type {
+            ASTNodeType::Pipeline(commands) => {
+                assert_eq!(commands[1].span, Span { start: 23, end: 28, line: 2, column: 14 });
+                match &commands[0].node_type {
+                    ASTNodeType::Command(_, args, _) => {
+                        assert_eq!(args[1].span, Span { start: 16, end: 20, line: 2, column: 7 })
+                    },
+                    _ => panic!()
+                }
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn parser_test_4() {
+        let mut p = Parser::new();
+        match p.parse("let = 5") {
+            Err(SyntaxError::UnexpectedToken(found, expected, span)) => {
+                assert_eq!(found, Token::AssignmentOp(String::from("=")));
+                assert_eq!(expected, vec!["identifier"]);
+                assert_eq!(span.column, 5);
+            },
+            _ => panic!()
+        }
+        match p.parse("(1 + 2") {
+            Err(SyntaxError::UnexpectedEOF(expected, _)) => assert_eq!(expected, vec!["`)`"]),
+            _ => panic!()
+        }
+        assert!(p.parse("echo ok").is_ok());
+    }
+
+    #[test]
+    fn parser_test_5() {
+        let mut p = Parser::new();
+        let parsed = p.parse("if 1 == 2 { ls } else if test -f x { 2 } else { }").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements.len(), 1);
+        match &statements[0].node_type {
+            ASTNodeType::If { cond, else_branch: Some(else_branch), .. } => {
+                assert!(matches!(cond.node_type, ASTNodeType::BinaryExpression(ref op, _, _) if op == "=="));
+                match &else_branch.node_type {
+                    ASTNodeType::If { cond, else_branch: Some(else_branch), .. } => {
+                        match &cond.node_type {
+                            ASTNodeType::Command(name, ..) => {
+                                assert_eq!(name.node_type, ASTNodeType::StringLiteral(String::from("test")))
+                            },
+                            _ => panic!()
+                        }
+                        assert_eq!(else_branch.node_type, ASTNodeType::Block(Vec::new()));
+                    },
+                    _ => panic!()
+                }
+            },
+            _ => panic!()
+        }
+        assert!(p.parse("if true { ls").is_err());
+    }
+
+    #[test]
+    fn parser_test_6() {
+        let mut p = Parser::new();
+        let parsed = p.parse("fn add(a, b) { return a + b }").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        match &statements[0].node_type {
+            ASTNodeType::FnDef { name, params, body } => {
+                assert_eq!(name, "add");
+                assert_eq!(params, &vec![String::from("a"), String::from("b")]);
+                match &body.node_type {
+                    ASTNodeType::Block(statements) => {
+                        assert!(matches!(statements[0].node_type, ASTNodeType::Return(Some(_))))
+                    },
+                    _ => panic!()
+                }
+            },
+            _ => panic!()
+        }
+        assert!(p.parse("fn f() { return }").is_ok());
+        assert!(p.parse("fn f() { return; echo unreachable }").is_ok());
+        assert!(p.parse("fn f(a b) { }").is_err());
+    }
+
+    #[test]
+    fn parser_test_7() {
+        let mut p = Parser::new();
+        let parsed = p.parse("echo \"a $x ${y}\\$z\" $HOME/bin 'lit $x'").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        let args = match &statements[0].node_type {
+            ASTNodeType::Command(_, args, _) => args,
+            _ => panic!()
+        };
+        let string = |s: &str| ASTNode { node_type: ASTNodeType::StringLiteral(String::from(s)), span: Span::default() };
+        let var = |s: &str| ASTNode { node_type: ASTNodeType::VarRef(String::from(s)), span: Span::default() };
+        assert_eq!(args[0].node_type, ASTNodeType::Interpolation(vec![string("a "), var("x"), string(" "), var("y"), string("$z")]));
+        assert_eq!(args[0].span, Span { start: 5, end: 19, line: 1, column: 6 });
+        match &args[0].node_type {
+            ASTNodeType::Interpolation(parts) => assert_eq!(parts[3].span, Span { start: 11, end: 15, line: 1, column: 12 }),
+            _ => panic!()
+        }
+        assert_eq!(args[1].node_type, ASTNodeType::Interpolation(vec![var("HOME"), string("/bin")]));
+        assert_eq!(args[2].node_type, ASTNodeType::StringLiteral(String::from("lit $x")));
+
+        assert!(matches!(p.parse("echo ${bad"), Err(SyntaxError::BadSubstitution(_))));
+    }
+
+    #[test]
+    fn parser_test_8() {
+        let mut p = Parser::new();
+        let parsed = p.parse("<in sort -r > \"$out\" 2>&1 | wc").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        let stages = match &statements[0].node_type {
+            ASTNodeType::Pipeline(stages) => stages,
+            _ => panic!()
+        };
+        let string = |s: &str| Box::from(ASTNode { node_type: ASTNodeType::StringLiteral(String::from(s)), span: Span::default() });
+        match &stages[0].node_type {
+            ASTNodeType::Command(name, args, redirects) => {
+                assert_eq!(name.node_type, ASTNodeType::StringLiteral(String::from("sort")));
+                assert_eq!(args.len(), 1);
+                assert_eq!(redirects[0].node_type, ASTNodeType::Redirect(RedirectOp::Input(0), Some(string("in"))));
+                assert!(matches!(&redirects[1].node_type, ASTNodeType::Redirect(RedirectOp::Output(1), Some(target))
+                    if matches!(target.node_type, ASTNodeType::Interpolation(_))));
+                assert_eq!(redirects[2].node_type, ASTNodeType::Redirect(RedirectOp::Duplicate(2, 1), None));
+                assert_eq!(redirects[1].span, Span { start: 12, end: 20, line: 1, column: 13 });
+            },
+            _ => panic!()
+        }
+
+        match p.parse("echo >") {
+            Err(SyntaxError::UnexpectedEOF(expected, _)) => assert_eq!(expected, vec!["file name"]),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn parser_test_9() {
+        let mut p = Parser::new();
+        let parsed = p.parse("sleep 1 | cat & echo a").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements.len(), 2);
+        match &statements[0].node_type {
+            ASTNodeType::Background(inner) => assert!(matches!(inner.node_type, ASTNodeType::Pipeline(_))),
+            _ => panic!()
+        }
+        assert_eq!(statements[0].span, Span { start: 0, end: 15, line: 1, column: 1 });
+        assert!(matches!(statements[1].node_type, ASTNodeType::Command(..)));
+    }
+
+    #[test]
+    fn parser_test_10() {
+        let mut p = Parser::new();
+        let parsed = p.parse("a | b && c || d &").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        let list = match &statements[0].node_type {
+            ASTNodeType::Background(list) => list,
+            _ => panic!()
+        };
+        // Chains group to the left, with pipelines binding tighter
+        let (left, right) = match &list.node_type {
+            ASTNodeType::AndOr(op, left, right) if op == "||" => (left, right),
+            _ => panic!()
+        };
+        assert!(matches!(right.node_type, ASTNodeType::Command(..)));
+        match &left.node_type {
+            ASTNodeType::AndOr(op, left, _) if op == "&&" => assert!(matches!(left.node_type, ASTNodeType::Pipeline(_))),
+            _ => panic!()
+        }
+        assert_eq!(list.span, Span { start: 0, end: 15, line: 1, column: 1 });
+
+        match p.parse("test a &&") {
+            Err(SyntaxError::UnexpectedEOF(expected, _)) => assert_eq!(expected, vec!["command"]),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn parser_test_11() {
+        let mut p = Parser::new();
+        let parsed = p.parse(";echo a;echo b ;; let x = 1; \n\nls |\n  wc -l\nfn f() {\n  echo x\n\n  echo y; }\n").unwrap();
+        let statements = match parsed.node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements.len(), 5);
+        match &statements[1].node_type {
+            ASTNodeType::Command(name, args, _) => {
+                assert_eq!(name.node_type, ASTNodeType::StringLiteral(String::from("echo")));
+                assert_eq!(args.len(), 1);
+            },
+            _ => panic!()
+        }
+        assert!(matches!(statements[2].node_type, ASTNodeType::VarDef(..)));
+        assert!(matches!(statements[3].node_type, ASTNodeType::Pipeline(_)));
+        match &statements[4].node_type {
+            ASTNodeType::FnDef { body, .. } => assert!(matches!(&body.node_type, ASTNodeType::Block(body) if body.len() == 2)),
+            _ => panic!()
+        }
+
+        match p.parse("echo a >\nfile") {
+            Err(SyntaxError::UnexpectedToken(Token::Separator('\n'), expected, span)) => {
+                assert_eq!(expected, vec!["file name"]);
+                assert_eq!(span, Span { start: 8, end: 9, line: 1, column: 9 });
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn parser_test_12() {
+        let mut p = Parser::new();
+        // A newline ends an expression, but not after an operator
+        let statements = match p.parse("let x = 2\n/bin/echo hi\nlet y = x +\n  1\nx").unwrap().node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements.len(), 4);
+        assert!(matches!(statements[1].node_type, ASTNodeType::Command(..)));
+        assert!(matches!(&statements[2].node_type, ASTNodeType::VarDef(name, _) if name == "y"));
+
+        assert!(p.parse("if x\n{\n  1\n}\nelse\n{\n  2\n}").is_ok());
+        assert!(p.parse("fn f(\n  a,\n  b\n) { }").is_ok());
+        assert!(p.parse("echo a |\n  cat &&\n  echo b").is_ok());
+        assert!(matches!(p.parse("let x =\n"), Err(SyntaxError::UnexpectedEOF(..))));
+        // Only newlines carry a statement on, not `;`
+        for input in ["echo a &&; echo b", "echo a |; cat", "let x = 1 +; 2"] {
+            assert!(matches!(p.parse(input), Err(SyntaxError::UnexpectedToken(Token::Separator(';'), ..))), "{}", input);
+        }
+        // A newline before any other keyword ends the `if`
+        let statements = match p.parse("if x { 1 }\nif y { 2 }").unwrap().node_type {
+            ASTNodeType::StatementList(statements) => statements,
+            _ => panic!()
+        };
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn parser_test_13() {
+        let mut p = Parser::new();
+        let first = |program: ASTNode| match program.node_type {
+            ASTNodeType::StatementList(mut statements) => statements.remove(0).node_type,
+            _ => panic!()
+        };
+        // Values followed by more words are commands
+        for input in ["$e hello", "$x /", "$x / | cat", "\"$ls\" -la", "${e:-echo} a && b"] {
+            match first(p.parse(input).unwrap()) {
+                ASTNodeType::Command(..) | ASTNodeType::AndOr(..) | ASTNodeType::Pipeline(_) => {},
+                other => panic!("{}: {:?}", input, other)
+            }
+        }
+        for input in ["$x / 2", "$x-1", "$x == \"a\"", "$x\n/bin/ls"] {
+            assert!(matches!(first(p.parse(input).unwrap()), ASTNodeType::BinaryExpression(..) | ASTNodeType::VarRef(_)), "{}", input);
+        }
+    }
+}
@@ -1,74 +1,174 @@
 use regex::{ Regex, RegexSet };
 use lazy_static::*;
-use std::panic;
-use substring::Substring;
 
-pub mod token;
-use token::Token;
-
-pub struct TokenValue {
-    number: f64,
-    string: String,
-    boolean: bool
-}
+use crate::parser::SyntaxError;
 
+pub mod token;
+use token::{ExpansionOp, RedirectOp, Span, SpannedToken, Token};
 
 #[derive(Debug)]
 pub struct Tokenizer {
     program: String,
-    tokens: Vec<Token>,
     cursor: usize,
-    len: usize
+    token_start: usize,
+    line_starts: Vec<usize>,
+    /// The end of the line holding the last here-document operator, and
+    /// where the text after its body resumes. Reading past the end of that
+    /// line skips over the body.
+    heredoc_end: Option<(usize, usize)>
+}
+
+/// Where a tokenizer has read up to, saved so it can read ahead and then
+/// go back.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    cursor: usize,
+    token_start: usize,
+    heredoc_end: Option<(usize, usize)>
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Tokenizer {
     pub fn new() -> Self {
         Tokenizer {
             program: String::from(""),
-            tokens: Vec::new(),
             cursor: 0,
-            len: 0
+            token_start: 0,
+            line_starts: vec![0],
+            heredoc_end: None
         }
     }
 
     pub fn from(input: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
         Tokenizer {
             program: String::from(input),
-            tokens: Vec::new(),
             cursor: 0,
-            len: input.len()
+            token_start: 0,
+            line_starts,
+            heredoc_end: None
         }
     }
 
-    pub fn init(&mut self, input: &str) {
-        self.program = String::from(input);
-        self.len = input.len();
+    /// Builds the span of the text between `start` and `end`, with a 1-based
+    /// line and column for the start position.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let line = match self.line_starts.binary_search(&start) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1
+        };
+        let column = self.program[self.line_starts[line]..start].chars().count() + 1;
+        Span {
+            start,
+            end,
+            line: line + 1,
+            column
+        }
     }
 
-    pub fn has_more_tokens(&self) -> bool {
-        self.cursor < self.len
+    fn spanned(&self, token: Token) -> SpannedToken {
+        SpannedToken {
+            token,
+            span: self.span(self.token_start, self.cursor)
+        }
     }
 
-    pub fn get_next_token(&mut self, consume: bool) -> Token {
+    /// Saves where the tokenizer has read up to, for `restore`.
+    pub fn position(&self) -> Position {
+        Position {
+            cursor: self.cursor,
+            token_start: self.token_start,
+            heredoc_end: self.heredoc_end
+        }
+    }
+
+    /// Goes back to a position saved before reading ahead.
+    pub fn restore(&mut self, position: Position) {
+        self.cursor = position.cursor;
+        self.token_start = position.token_start;
+        self.heredoc_end = position.heredoc_end;
+    }
+
+    /// Moves the cursor back to the start of the most recently returned token,
+    /// so that it can be read again in a different mode.
+    pub fn rewind(&mut self) {
+        self.cursor = self.token_start;
+    }
+
+    /// Skips whitespace and comments, leaving the cursor on the next token.
+    /// Here-document bodies are skipped along with the newline before them.
+    fn skip_ignored(&mut self) {
         lazy_static! {
-            static ref IDENT_PATTERN: Regex = Regex::new(r#"[^\s"(){}]+"#).unwrap();
-            static ref NUMBER_PATTERN: Regex = Regex::new(r"^\d+\.?\d*").unwrap();
-            static ref STRING_PATTERN: Regex = Regex::new(r#"^".*""#).unwrap();
             static ref WHITESPACE_PATTERN: Regex = Regex::new(r"^[\s]+").unwrap();
-            static ref LINE_TERM_PATTERN: Regex = Regex::new(r"^\n").unwrap();
-            static ref LOGICAL_OP_SET: RegexSet = RegexSet::new(&[
+        }
+        loop {
+            let temp_program = &self.program[self.cursor..];
+            if let Some(ws) = WHITESPACE_PATTERN.find(temp_program) {
+                let end = self.cursor + ws.end();
+                match self.heredoc_end {
+                    Some((line_end, resume)) if (self.cursor..end).contains(&line_end) => {
+                        self.cursor = resume;
+                        self.heredoc_end = None;
+                    },
+                    _ => self.cursor = end
+                }
+            } else if temp_program.starts_with('#') {
+                self.cursor += temp_program.find('\n').unwrap_or(temp_program.len());
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Skips whitespace and comments like `skip_ignored`. A newline among
+    /// them ends a statement, so it comes back as a Separator.
+    fn skip_to_token(&mut self) -> Option<SpannedToken> {
+        let start = self.cursor;
+        self.skip_ignored();
+        let offset = self.program[start..self.cursor].find('\n')?;
+        // Left at the next token, so a rewind reads that rather than the newline
+        self.token_start = self.cursor;
+        Some(SpannedToken {
+            token: Token::Separator('\n'),
+            span: self.span(start + offset, start + offset + 1)
+        })
+    }
+
+    pub fn get_next_token(&mut self) -> Result<SpannedToken, SyntaxError> {
+        lazy_static! {
+            static ref IDENT_PATTERN: Regex = Regex::new(r#"^[^\s"'(){},;]+"#).unwrap();
+            static ref NUMBER_PATTERN: Regex = Regex::new(r"^\d+\.?\d*").unwrap();
+            static ref STRING_PATTERN: Regex = Regex::new(r#"^"(?:[^"\\]|\\.)*""#).unwrap();
+            static ref RAW_STRING_PATTERN: Regex = Regex::new(r"^'[^']*'").unwrap();
+            static ref LOGICAL_OP_SET: RegexSet = RegexSet::new([
                 r"^\|\|",
+                r"^\|&",
                 r"^\|",
                 r"^&&",
                 r"^&",
             ]).unwrap();
-            static ref BINARY_OP_SET: RegexSet = RegexSet::new(&[
+            static ref BINARY_OP_SET: RegexSet = RegexSet::new([
                 r"^\+",
                 r"^-",
                 r"^\*",
                 r"^/"
             ]).unwrap();
-            static ref ASSIGNMENT_OP_SET: RegexSet = RegexSet::new(&[
+            static ref COMPARISON_OP_SET: RegexSet = RegexSet::new([
+                r"^==",
+                r"^!=",
+                r"^<=",
+                r"^>=",
+                r"^<",
+                r"^>"
+            ]).unwrap();
+            static ref ASSIGNMENT_OP_SET: RegexSet = RegexSet::new([
                 r"^\+=",
                 r"^-=",
                 r"^\*=",
@@ -76,162 +176,446 @@ impl Tokenizer {
                 r"^="
             ]).unwrap();
         }
-        let mut consumed_len = 0;
+        if let Some(separator) = self.skip_to_token() {
+            return Ok(separator)
+        }
+        self.token_start = self.cursor;
+
         let result: Token;
         let tok_len: usize;
-        let temp_program: &str = self.program.substring(self.cursor, self.program.len());
-        match WHITESPACE_PATTERN.captures_iter(temp_program).next() {
-            Some(cap) => {
-                self.cursor += cap.len();
-                if consume {
-                    consumed_len += cap.len();
-                }
-                return self.get_next_token(consume);
-            },
-            None => {}
+        let temp_program: &str = &self.program[self.cursor..];
+        if temp_program.starts_with('$') {
+            if let Some((var_ref, len)) = self.var_ref_at(self.cursor)? {
+                self.cursor += len;
+                return Ok(self.spanned(var_ref))
+            }
         }
-        println!("{}", temp_program);
         match temp_program {
-            _ if temp_program.starts_with("#") => {
-                let comment_chars: std::str::Chars = temp_program.chars();
-                for (index, chr) in comment_chars.enumerate() {
-                    if let '\n' = chr {
-                        self.cursor += index;
-                        if consume {
-                            consumed_len += index;
-                        }
-                        break;
-                    }
-                }
-                return self.get_next_token(consume);
-            },
-            _ if temp_program.starts_with("let")
+            _ if starts_with_word(temp_program, "let")
                 => (tok_len, result) = (3, Token::Let),
-            _ if temp_program.starts_with("(")
+            _ if temp_program.starts_with('(')
                 => (tok_len, result) = (1, Token::OpenParen),
-            _ if temp_program.starts_with(")")
+            _ if temp_program.starts_with(')')
                 => (tok_len, result) = (1, Token::CloseParen),
-            _ if temp_program.starts_with("{")
+            _ if temp_program.starts_with('{')
                 => (tok_len, result) = (1, Token::OpenBrace),
-            _ if temp_program.starts_with("}")
+            _ if temp_program.starts_with('}')
                 => (tok_len, result) = (1, Token::CloseBrace),
-            _ if temp_program.starts_with("true")
+            _ if temp_program.starts_with(',')
+                => (tok_len, result) = (1, Token::Comma),
+            _ if temp_program.starts_with(';')
+                => (tok_len, result) = (1, Token::Separator(';')),
+            _ if starts_with_word(temp_program, "true")
                 => (tok_len, result) = (4, Token::BoolLiteral(true)),
-            _ if temp_program.starts_with("false")
+            _ if starts_with_word(temp_program, "false")
                 => (tok_len, result) = (5, Token::BoolLiteral(false)),
-            _ if temp_program.starts_with("if")
+            _ if starts_with_word(temp_program, "if")
                 => (tok_len, result) = (2, Token::Keyword(String::from("if"))),
-            _ if temp_program.starts_with("else")
+            _ if starts_with_word(temp_program, "else")
                 => (tok_len, result) = (4, Token::Keyword(String::from("else"))),
-            _ if temp_program.starts_with("fn")
+            _ if starts_with_word(temp_program, "fn")
                 => (tok_len, result) = (2, Token::Keyword(String::from("fn"))),
-            _ if temp_program.starts_with("return")
+            _ if starts_with_word(temp_program, "return")
                 => (tok_len, result) = (6, Token::Keyword(String::from("return"))),
-            number if NUMBER_PATTERN.is_match(&temp_program) => {
-                let num_str = NUMBER_PATTERN.captures_iter(number).next().unwrap().get(0).unwrap().as_str();
+            number if NUMBER_PATTERN.is_match(temp_program) => {
+                let num_str = NUMBER_PATTERN.find(number).unwrap().as_str();
                 let n = match num_str.parse::<f64>() {
                     Ok(num) => num,
-                    Err(e) => {println!("{}", e); panic!()}
+                    Err(_) => return Err(SyntaxError::InvalidNumber(
+                        String::from(num_str),
+                        self.span(self.cursor, self.cursor + num_str.len())
+                    ))
                 };
                 (tok_len, result) = (num_str.len(), Token::NumberLiteral(n));
             },
-            string if STRING_PATTERN.is_match(&temp_program) => {
-                let string = STRING_PATTERN.captures_iter(string).next().unwrap().get(0).unwrap().as_str();
-                (tok_len, result) = (string.len(), Token::StringLiteral(String::from(string))); //.substring(1, string.len()-1)
+            string if STRING_PATTERN.is_match(temp_program) => {
+                let string = STRING_PATTERN.find(string).unwrap().as_str();
+                (tok_len, result) = (string.len(), Token::StringLiteral(String::from(&string[1..string.len() - 1])));
+            },
+            string if RAW_STRING_PATTERN.is_match(temp_program) => {
+                let string = RAW_STRING_PATTERN.find(string).unwrap().as_str();
+                (tok_len, result) = (string.len(), Token::RawString(String::from(&string[1..string.len() - 1])));
+            },
+            op if COMPARISON_OP_SET.is_match(temp_program) => {
+                let op = first_match(&COMPARISON_OP_SET, op);
+                (tok_len, result) = (op.len(), Token::BinaryOp(String::from(op)));
             },
-            op if ASSIGNMENT_OP_SET.is_match(&temp_program) => {
-                let op: &str = Regex::new(
-                    &ASSIGNMENT_OP_SET
-                    .patterns()[
-                        ASSIGNMENT_OP_SET
-                        .matches(temp_program)
-                        .into_iter()
-                        .next().unwrap()
-                    ]
-                ).unwrap()
-                    .captures_iter(op)
-                    .next().unwrap()
-                    .get(0).unwrap()
-                    .as_str();
+            op if ASSIGNMENT_OP_SET.is_match(temp_program) => {
+                let op = first_match(&ASSIGNMENT_OP_SET, op);
                 (tok_len, result) = (op.len(), Token::AssignmentOp(String::from(op)));
             },
-            op if LOGICAL_OP_SET.is_match(&temp_program) => {
-                let op: &str = Regex::new(
-                    &LOGICAL_OP_SET
-                    .patterns()[
-                        LOGICAL_OP_SET
-                        .matches(temp_program)
-                        .into_iter()
-                        .next().unwrap()
-                    ]
-                ).unwrap()
-                    .captures_iter(op)
-                    .next().unwrap()
-                    .get(0).unwrap()
-                    .as_str();
+            op if LOGICAL_OP_SET.is_match(temp_program) => {
+                let op = first_match(&LOGICAL_OP_SET, op);
                 tok_len = op.len();
                 result = match op {
                     "|" => Token::Pipe,
+                    "|&" => Token::PipeAll,
                     other => Token::LogicalOp(String::from(other))
                 };
             },
-            op if BINARY_OP_SET.is_match(&temp_program) => {
-                let op: &str = Regex::new(
-                    &BINARY_OP_SET
-                    .patterns()[
-                        BINARY_OP_SET
-                        .matches(temp_program)
-                        .into_iter()
-                        .next().unwrap()
-                    ]
-                ).unwrap()
-                    .captures_iter(op)
-                    .next().unwrap()
-                    .get(0).unwrap()
-                    .as_str();
+            op if BINARY_OP_SET.is_match(temp_program) => {
+                let op = first_match(&BINARY_OP_SET, op);
                 (tok_len, result) = (op.len(), Token::BinaryOp(String::from(op)));
             },
-            ident if IDENT_PATTERN.is_match(&temp_program) => {
-                let ident = IDENT_PATTERN.captures_iter(ident).next().unwrap().get(0).unwrap().as_str();
+            _ if temp_program.starts_with(char::is_control)
+                => return Err(self.unknown_character(self.cursor)),
+            ident if IDENT_PATTERN.is_match(temp_program) => {
+                let ident = IDENT_PATTERN.find(ident).unwrap().as_str();
                 (tok_len, result) = (ident.len(), Token::Identifier(String::from(ident)));
             },
-            "" => return Token::Empty,
-            bad_tok => panic!("Unknown token {}...", bad_tok)
+            "" => return Ok(self.end_of_input()),
+            // Only an opening double quote without a closing one is left unmatched
+            _ => return Err(SyntaxError::UnterminatedString(self.span(self.cursor, self.program.len())))
         }
 
-        self.tokens.push(result.clone());
-
         self.cursor += tok_len;
-        self.cursor -= consumed_len;
 
-        result
+        Ok(self.spanned(result))
+    }
+
+    /**
+     *  Reads the next piece of a shell word, as used for command arguments.
+     *
+     *  Words run until unquoted whitespace or an operator character, so
+     *  `-a`, `/tmp` and `file.txt` each come back as one Identifier. A word
+     *  like `"$HOME"/bin` is made of several pieces, which the parser joins
+     *  back together because their spans touch:
+     *
+     *  - unquoted text, with backslash escapes removed, as an Identifier
+     *  - double-quoted text as a StringLiteral, still to be interpolated
+     *  - single-quoted text as a RawString
+     *  - `$name` and `${name}` as a VarRef, and other `${...}` forms as a
+     *    ParamExpansion
+     *
+     *  Redirections such as `>`, `2>>` and `2>&1` come back as a Redirect,
+     *  and other operators are tokenized as usual.
+     */
+    pub fn get_next_word(&mut self) -> Result<SpannedToken, SyntaxError> {
+        lazy_static! {
+            static ref REDIRECT_PATTERN: Regex = Regex::new(r"^(?:&>>?|[0-9]?(?:<<<|<<-?|>>|>&[0-9]|>|<))").unwrap();
+        }
+        // Within a word, a `#` is just another character rather than a comment
+        let in_word = |program: &str, cursor: usize| program[..cursor]
+            .chars()
+            .next_back()
+            .is_some_and(|c| !c.is_whitespace() && !is_operator_char(c));
+        if !in_word(&self.program, self.cursor) || self.program[self.cursor..].starts_with(char::is_whitespace) {
+            if let Some(separator) = self.skip_to_token() {
+                return Ok(separator)
+            }
+        }
+        self.token_start = self.cursor;
+
+        let temp_program = &self.program[self.cursor..];
+        if let Some(op) = REDIRECT_PATTERN.find(temp_program) {
+            // A digit only names a file descriptor at the start of a word
+            if !op.as_str().starts_with(|c: char| c.is_ascii_digit()) || !in_word(&self.program, self.cursor) {
+                self.cursor += op.end();
+                let op = op.as_str();
+                if op.ends_with("<<") && !op.ends_with("<<<") || op.ends_with("<<-") {
+                    let fd = op.trim_end_matches(['<', '-']).parse().unwrap_or(0);
+                    return self.here_doc(fd, op.ends_with('-'))
+                }
+                return Ok(self.spanned(Token::Redirect(redirect_op(op))))
+            }
+        }
+        match temp_program.chars().next() {
+            None => return Ok(self.end_of_input()),
+            Some(c) if is_operator_char(c) => return self.get_next_token(),
+            Some(quote @ ('"' | '\'')) => {
+                let content_len = match quoted_len(&temp_program[1..], quote) {
+                    Some(len) => len,
+                    None => return Err(SyntaxError::UnterminatedString(self.span(self.cursor, self.program.len())))
+                };
+                let content = String::from(&temp_program[1..content_len + 1]);
+                self.cursor += content_len + 2;
+                return Ok(self.spanned(match quote {
+                    '"' => Token::StringLiteral(content),
+                    _ => Token::RawString(content)
+                }))
+            },
+            Some('$') => if let Some((var_ref, len)) = self.var_ref_at(self.cursor)? {
+                self.cursor += len;
+                return Ok(self.spanned(var_ref))
+            },
+            _ => {}
+        }
+
+        let mut word = String::new();
+        let mut chars = temp_program.char_indices().peekable();
+        let mut end = temp_program.len();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c.is_whitespace() || is_operator_char(c) || c == '"' || c == '\'' => {
+                    end = index;
+                    break;
+                },
+                '$' if index > 0 && self.var_ref_at(self.cursor + index)?.is_some() => {
+                    end = index;
+                    break;
+                },
+                c if c.is_control() => return Err(self.unknown_character(self.cursor + index)),
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        word.push(escaped);
+                    }
+                },
+                other => word.push(other)
+            }
+        }
+
+        self.cursor += end;
+
+        Ok(self.spanned(Token::Identifier(word)))
+    }
+
+    /**
+     *  Reads a variable reference starting at `position`: `$name`, `${name}`
+     *  or a parameter expansion such as `${name:-word}`. Returns the VarRef
+     *  or ParamExpansion token and the length of its text, or `None` if the
+     *  `$` doesn't start a reference and is just text.
+     */
+    pub fn var_ref_at(&self, position: usize) -> Result<Option<(Token, usize)>, SyntaxError> {
+        lazy_static! {
+            static ref VAR_PATTERN: Regex = Regex::new(r"^\$([A-Za-z_][A-Za-z0-9_]*|[0-9?!$#@*])").unwrap();
+            static ref BRACED_VAR_PATTERN: Regex = Regex::new(
+                r"^\$\{(#?)([A-Za-z_][A-Za-z0-9_]*|[0-9?!$#@*])(\}|:?[-=?]|##?|%%?|//?)"
+            ).unwrap();
+        }
+        let text = &self.program[position..];
+        if !text.starts_with("${") {
+            return Ok(VAR_PATTERN
+                .captures(text)
+                .map(|captures| (Token::VarRef(String::from(&captures[1])), captures[0].len())))
+        }
+
+        let bad_substitution = || {
+            let end = find_unnested(&text[2..], '}').map_or(self.program.len(), |end| position + end + 3);
+            SyntaxError::BadSubstitution(self.span(position, end))
+        };
+        let captures = match BRACED_VAR_PATTERN.captures(text) {
+            Some(captures) => captures,
+            None => return Err(bad_substitution())
+        };
+        let name = String::from(&captures[2]);
+        let length = !captures[1].is_empty();
+        let op = match &captures[3] {
+            "}" if length => return Ok(Some((Token::ParamExpansion(name, ExpansionOp::Length, vec![]), captures[0].len()))),
+            "}" => return Ok(Some((Token::VarRef(name), captures[0].len()))),
+            _ if length => return Err(bad_substitution()),
+            ":-" => ExpansionOp::Default(true),
+            "-" => ExpansionOp::Default(false),
+            ":=" => ExpansionOp::Assign(true),
+            "=" => ExpansionOp::Assign(false),
+            ":?" => ExpansionOp::Error(true),
+            "?" => ExpansionOp::Error(false),
+            "#" => ExpansionOp::RemovePrefix(false),
+            "##" => ExpansionOp::RemovePrefix(true),
+            "%" => ExpansionOp::RemoveSuffix(false),
+            "%%" => ExpansionOp::RemoveSuffix(true),
+            "/" => ExpansionOp::Replace(false),
+            _ => ExpansionOp::Replace(true)
+        };
+
+        let word_start = position + captures[0].len();
+        let word = match find_unnested(&self.program[word_start..], '}') {
+            Some(len) => &self.program[word_start..word_start + len],
+            None => return Err(bad_substitution())
+        };
+        let mut words = vec![(String::from(word), word_start)];
+        // The pattern of a replacement ends at the first unescaped slash
+        if let (ExpansionOp::Replace(_), Some(slash)) = (op, find_unnested(word, '/')) {
+            words = vec![
+                (String::from(&word[..slash]), word_start),
+                (String::from(&word[slash + 1..]), word_start + slash + 1)
+            ];
+        }
+        let len = word_start + word.len() + 1 - position;
+        Ok(Some((Token::ParamExpansion(name, op, words), len)))
+    }
+
+    /**
+     *  Reads the delimiter of a here-document, whose operator has just been
+     *  read, and its body from the lines after the current one (or after
+     *  the previous here-document on the same line). With `strip_tabs`, as
+     *  for `<<-`, leading tabs are removed from each line. Quoting any part
+     *  of the delimiter turns off expansion in the body.
+     */
+    fn here_doc(&mut self, fd: i32, strip_tabs: bool) -> Result<SpannedToken, SyntaxError> {
+        while self.program[self.cursor..].starts_with([' ', '\t']) {
+            self.cursor += 1;
+        }
+
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        let mut chars = self.program[self.cursor..].char_indices().peekable();
+        let mut delimiter_len = self.program.len() - self.cursor;
+        while let Some((index, c)) = chars.next() {
+            match c {
+                quote @ ('"' | '\'') => {
+                    quoted = true;
+                    let len = quoted_len(&self.program[self.cursor + index + 1..], quote)
+                        .ok_or_else(|| SyntaxError::UnterminatedString(self.span(self.cursor + index, self.program.len())))?;
+                    delimiter += &self.program[self.cursor + index + 1..self.cursor + index + 1 + len];
+                    while chars.next_if(|(next, _)| *next <= index + len + 1).is_some() {}
+                },
+                '\\' => {
+                    quoted = true;
+                    if let Some((_, escaped)) = chars.next() {
+                        delimiter.push(escaped);
+                    }
+                },
+                c if c.is_whitespace() || is_operator_char(c) => {
+                    delimiter_len = index;
+                    break;
+                },
+                c => delimiter.push(c)
+            }
+        }
+        if delimiter_len == 0 {
+            let found = self.get_next_token()?;
+            return Err(match found.token {
+                Token::Empty => SyntaxError::UnexpectedEOF(vec!["delimiter"], found.span),
+                token => SyntaxError::UnexpectedToken(token, vec!["delimiter"], found.span)
+            })
+        }
+        self.cursor += delimiter_len;
+
+        let unterminated = SyntaxError::UnterminatedHereDoc(delimiter.clone(), self.span(self.token_start, self.cursor));
+        let (line_end, body_start) = match self.heredoc_end {
+            Some((line_end, resume)) => (line_end, resume),
+            None => match self.program[self.cursor..].find('\n') {
+                Some(offset) => (self.cursor + offset, self.cursor + offset + 1),
+                None => return Err(unterminated)
+            }
+        };
+
+        let mut lines = vec![];
+        let mut line_start = body_start;
+        loop {
+            if line_start >= self.program.len() {
+                return Err(unterminated)
+            }
+            let next_line = self.program[line_start..].find('\n').map_or(self.program.len(), |offset| line_start + offset + 1);
+            let mut start = line_start;
+            if strip_tabs {
+                start += self.program[line_start..next_line].len() - self.program[line_start..next_line].trim_start_matches('\t').len();
+            }
+            let line = &self.program[start..next_line];
+            if line.trim_end_matches('\n') == delimiter {
+                self.heredoc_end = Some((line_end, next_line));
+                break;
+            }
+            lines.push((String::from(line), start));
+            line_start = next_line;
+        }
+        Ok(self.spanned(Token::HereDoc(fd, lines, !quoted)))
+    }
+
+    /// The Empty token, placed right after the last non-whitespace text so
+    /// errors about a missing token point at the end of the input's last line.
+    fn end_of_input(&mut self) -> SpannedToken {
+        self.token_start = self.program.trim_end().len();
+        self.spanned(Token::Empty)
+    }
+
+    fn unknown_character(&self, position: usize) -> SyntaxError {
+        let c = self.program[position..].chars().next().unwrap();
+        SyntaxError::UnknownCharacter(c, self.span(position, position + c.len_utf8()))
     }
+}
 
-    pub fn exec(&mut self) -> &Self {
-        while self.has_more_tokens() {
-            self.get_next_token(true);
+/// The length of quoted text up to, but not including, the closing `quote`.
+/// Backslashes escape characters in double quotes but not in single quotes.
+pub(crate) fn quoted_len(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => { chars.next(); },
+            c if c == quote => return Some(index),
+            _ => {}
         }
-        self
     }
+    None
+}
+
+/// The position of the first `target` in `text` which isn't escaped by a
+/// backslash, quoted or inside a nested `${...}`.
+fn find_unnested(text: &str, target: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            quote @ ('"' | '\'') => {
+                let close = index + 1 + quoted_len(&text[index + 1..], quote)?;
+                while chars.next_if(|(next, _)| *next <= close).is_some() {}
+            },
+            '$' if chars.next_if(|(_, next)| *next == '{').is_some() => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if c == target && depth == 0 => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Characters which end a shell word when they appear unquoted.
+pub(crate) fn is_operator_char(c: char) -> bool {
+    matches!(c, '|' | '&' | '(' | ')' | '{' | '}' | '<' | '>' | ';')
+}
+
+/// Converts the text of a redirection operator to a RedirectOp. A leading
+/// digit names the file descriptor to redirect, which otherwise defaults
+/// to stdin for `<` and stdout for the rest.
+fn redirect_op(op: &str) -> RedirectOp {
+    let digits = op.find(|c: char| !c.is_ascii_digit()).unwrap_or(op.len());
+    let fd = op[..digits].parse::<i32>().ok();
+    match &op[digits..] {
+        "&>" => RedirectOp::All(false),
+        "&>>" => RedirectOp::All(true),
+        "<" => RedirectOp::Input(fd.unwrap_or(0)),
+        "<<<" => RedirectOp::HereString(fd.unwrap_or(0)),
+        ">" => RedirectOp::Output(fd.unwrap_or(1)),
+        ">>" => RedirectOp::Append(fd.unwrap_or(1)),
+        duplicate => RedirectOp::Duplicate(fd.unwrap_or(1), duplicate[2..].parse().unwrap())
+    }
+}
+
+/// Whether `program` starts with `word` as a whole word rather than as the
+/// prefix of a longer identifier, so `iffy` isn't read as `if`.
+fn starts_with_word(program: &str, word: &str) -> bool {
+    program.starts_with(word) && match program[word.len()..].chars().next() {
+        Some(c) => !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/')),
+        None => true
+    }
+}
+
+/// Returns the text matched by the first pattern in `set` matching `program`.
+fn first_match<'a>(set: &RegexSet, program: &'a str) -> &'a str {
+    let index = set.matches(program).into_iter().next().unwrap();
+    Regex::new(&set.patterns()[index]).unwrap()
+        .find(program).unwrap()
+        .as_str()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Token, Tokenizer};
+    use super::{ExpansionOp, RedirectOp, Span, SyntaxError, Token, Tokenizer};
 
     #[test]
     fn tokenizer_test_1() {
         let mut t = Tokenizer::from("if 1.0 25.0 else 3.0");
 
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("if")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(1.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(25.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("else")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(3.0))
     }
 
@@ -240,11 +624,14 @@ mod tests {
         let mut t = Tokenizer::from("#test\n
         1.0 && 2.0");
 
-        let tok = t.get_next_token(true);
+        // The comment and blank line make one separator
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok, Token::Separator('\n'));
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(1.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::LogicalOp(String::from("&&")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(2.0));
     }
 
@@ -252,23 +639,23 @@ mod tests {
     fn tokenizer_test_3() {
         let mut t = Tokenizer::from("if true { 1 } else { 2 }");
 
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("if")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::BoolLiteral(true));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::OpenBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(1.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::CloseBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("else")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::OpenBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(2.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::CloseBrace);
     }
 
@@ -276,15 +663,15 @@ mod tests {
     fn tokenizer_test_4() {
         let mut t = Tokenizer::from("if \"test\" { 1 }");
 
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("if")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::StringLiteral(String::from("test")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::OpenBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(1.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::CloseBrace);
     }
 
@@ -292,40 +679,225 @@ mod tests {
     fn tokenizer_test_5() {
         let mut t = Tokenizer::from("if \"test\" { ls -a }");
 
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("if")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::StringLiteral(String::from("test")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::OpenBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Identifier(String::from("ls")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::BinaryOp(String::from("-")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Identifier(String::from("a")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::CloseBrace);
     }
 
 
+    #[test]
+    fn tokenizer_test_7() {
+        let mut t = Tokenizer::from("let x = 1\n  echo \"é\" done");
+
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok.span, Span { start: 0, end: 3, line: 1, column: 1 });
+        t.get_next_token().unwrap();
+        t.get_next_token().unwrap();
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok.span, Span { start: 8, end: 9, line: 1, column: 9 });
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok, Token::Separator('\n'));
+        assert_eq!(tok.span, Span { start: 9, end: 10, line: 1, column: 10 });
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok.span, Span { start: 12, end: 16, line: 2, column: 3 });
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok, Token::StringLiteral(String::from("é")));
+        assert_eq!(tok.span, Span { start: 17, end: 21, line: 2, column: 8 });
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok.span, Span { start: 22, end: 26, line: 2, column: 12 });
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok, Token::Empty);
+        assert_eq!(tok.span, Span { start: 26, end: 26, line: 2, column: 16 });
+    }
+
     #[test]
     fn tokenizer_test_6() {
         let mut t = Tokenizer::from("if \"test\" { 2 + 2 }");
 
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::Keyword(String::from("if")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::StringLiteral(String::from("test")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::OpenBrace);
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(2.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::BinaryOp(String::from("+")));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::NumberLiteral(2.0));
-        let tok = t.get_next_token(true);
+        let tok = t.get_next_token().unwrap();
         assert_eq!(tok, Token::CloseBrace);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tokenizer_test_8() {
+        let mut t = Tokenizer::from("echo \"unterminated");
+        assert!(t.get_next_word().is_ok());
+        match t.get_next_word() {
+            Err(SyntaxError::UnterminatedString(span)) => assert_eq!(span.column, 6),
+            _ => panic!()
+        }
+
+        let mut t = Tokenizer::from("let \x1b");
+        assert!(t.get_next_token().is_ok());
+        match t.get_next_token() {
+            Err(SyntaxError::UnknownCharacter(c, span)) => {
+                assert_eq!(c, '\x1b');
+                assert_eq!(span.column, 5);
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn tokenizer_test_9() {
+        let mut t = Tokenizer::from("if \"a\" == \"b\" { x = 1 }");
+
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok, Token::Keyword(String::from("if")));
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok, Token::StringLiteral(String::from("a")));
+        let tok = t.get_next_token().unwrap();
+        assert!(matches!(tok.token, Token::BinaryOp(op) if op == "=="));
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok, Token::StringLiteral(String::from("b")));
+        let tok = t.get_next_token().unwrap();
+        assert_eq!(tok, Token::OpenBrace);
+        t.get_next_token().unwrap();
+        let tok = t.get_next_token().unwrap();
+        assert!(matches!(tok.token, Token::AssignmentOp(op) if op == "="));
+    }
+
+    #[test]
+    fn tokenizer_test_10() {
+        let mut t = Tokenizer::from("a\\ b#c \"$x\"/'$y'$z${w}x#y # comment\n|");
+        let mut words = vec![];
+        loop {
+            let tok = t.get_next_word().unwrap();
+            if tok == Token::Empty || tok == Token::Pipe {
+                break;
+            }
+            words.push(tok.token);
+        }
+        let expected = vec![
+            Token::Identifier(String::from("a b#c")),
+            Token::StringLiteral(String::from("$x")),
+            Token::Identifier(String::from("/")),
+            Token::RawString(String::from("$y")),
+            Token::VarRef(String::from("z")),
+            Token::VarRef(String::from("w")),
+            Token::Identifier(String::from("x#y")),
+            Token::Separator('\n')
+        ];
+        assert_eq!(words.len(), expected.len());
+        // Tokens compare by kind alone, so check what each one holds too
+        for (tok, expected) in words.iter().zip(&expected) {
+            match (tok, expected) {
+                (Token::Identifier(a), Token::Identifier(b))
+                | (Token::StringLiteral(a), Token::StringLiteral(b))
+                | (Token::RawString(a), Token::RawString(b))
+                | (Token::VarRef(a), Token::VarRef(b)) => assert_eq!(a, b),
+                (Token::Separator(a), Token::Separator(b)) => assert_eq!(a, b),
+                _ => panic!("{:?} != {:?}", tok, expected)
+            }
+        }
+        assert!(matches!(Tokenizer::from("${1x}").get_next_word(), Err(SyntaxError::BadSubstitution(_))));
+    }
+
+    #[test]
+    fn tokenizer_test_11() {
+        let t = Tokenizer::from("x ${a:-${b}} ${#a} ${a//\\//-}} ${a:}");
+        match t.var_ref_at(2) {
+            Ok(Some((Token::ParamExpansion(name, ExpansionOp::Default(true), words), 10))) => {
+                assert_eq!(name, "a");
+                assert_eq!(words, vec![(String::from("${b}"), 7)]);
+            },
+            _ => panic!()
+        }
+        assert!(matches!(t.var_ref_at(13), Ok(Some((Token::ParamExpansion(_, ExpansionOp::Length, _), 5)))));
+        match t.var_ref_at(19) {
+            Ok(Some((Token::ParamExpansion(_, ExpansionOp::Replace(true), words), 10))) => {
+                assert_eq!(words, vec![(String::from("\\/"), 24), (String::from("-"), 27)]);
+            },
+            _ => panic!()
+        }
+        match t.var_ref_at(31) {
+            Err(SyntaxError::BadSubstitution(span)) => assert_eq!((span.start, span.end), (31, 36)),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn tokenizer_test_12() {
+        let mut t = Tokenizer::from("cmd<in a2>x 2>>err >&2 &>all x>y");
+        let mut words = vec![];
+        loop {
+            let tok = t.get_next_word().unwrap();
+            if tok == Token::Empty {
+                break;
+            }
+            words.push(tok.token);
+        }
+        let word = |s: &str| Token::Identifier(String::from(s));
+        let expected = vec![
+            word("cmd"), Token::Redirect(RedirectOp::Input(0)), word("in"),
+            word("a2"), Token::Redirect(RedirectOp::Output(1)), word("x"),
+            Token::Redirect(RedirectOp::Append(2)), word("err"),
+            Token::Redirect(RedirectOp::Duplicate(1, 2)),
+            Token::Redirect(RedirectOp::All(false)), word("all"),
+            word("x"), Token::Redirect(RedirectOp::Output(1)), word("y")
+        ];
+        assert_eq!(words.len(), expected.len());
+        for (tok, expected) in words.iter().zip(&expected) {
+            match (tok, expected) {
+                (Token::Identifier(a), Token::Identifier(b)) => assert_eq!(a, b),
+                (Token::Redirect(a), Token::Redirect(b)) => assert_eq!(a, b),
+                _ => panic!("{:?} != {:?}", tok, expected)
+            }
+        }
+    }
+
+    #[test]
+    fn tokenizer_test_13() {
+        let mut t = Tokenizer::from("cat <<A <<-'B' x\na $x\nA\n\tb\n\tB\nnext");
+        assert!(matches!(t.get_next_word().unwrap().token, Token::Identifier(word) if word == "cat"));
+        match t.get_next_word().unwrap().token {
+            Token::HereDoc(0, lines, true) => assert_eq!(lines, vec![(String::from("a $x\n"), 17)]),
+            _ => panic!()
+        }
+        let tok = t.get_next_word().unwrap();
+        assert_eq!(tok.span, Span { start: 8, end: 14, line: 1, column: 9 });
+        match tok.token {
+            Token::HereDoc(0, lines, false) => assert_eq!(lines, vec![(String::from("b\n"), 25)]),
+            _ => panic!()
+        }
+        assert!(matches!(t.get_next_word().unwrap().token, Token::Identifier(x) if x == "x"));
+        assert_eq!(t.get_next_word().unwrap(), Token::Separator('\n'));
+        assert!(matches!(t.get_next_word().unwrap().token, Token::Identifier(next) if next == "next"));
+
+        let mut t = Tokenizer::from("cat <<EOF\nno end\n");
+        t.get_next_word().unwrap();
+        match t.get_next_word() {
+            Err(SyntaxError::UnterminatedHereDoc(delimiter, span)) => {
+                assert_eq!(delimiter, "EOF");
+                assert_eq!((span.start, span.end), (4, 9));
+            },
+            _ => panic!()
+        }
+        let mut t = Tokenizer::from("cat <<< word");
+        t.get_next_word().unwrap();
+        assert!(matches!(t.get_next_word().unwrap().token, Token::Redirect(RedirectOp::HereString(0))));
+    }
+}
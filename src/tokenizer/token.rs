@@ -1,11 +1,126 @@
 use std::fmt;
 
+/// A region of the source text. `start` and `end` are byte offsets, while
+/// `line` and `column` are 1-based and refer to the start of the region.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Span {
+    /// Returns a span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span
+}
+
+impl PartialEq<Token> for SpannedToken {
+    fn eq(&self, other: &Token) -> bool {
+        self.token == *other
+    }
+}
+
+/// The operator of a `${name<op>word}` parameter expansion. The flag on
+/// the first three is set by the `:` forms, which also treat a variable
+/// set to the empty string as unset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpansionOp {
+    /// `${name:-word}`: the word if the variable is unset.
+    Default(bool),
+    /// `${name:=word}`: as `Default`, but also assigns the word.
+    Assign(bool),
+    /// `${name:?word}`: an error with the word as message if unset.
+    Error(bool),
+    /// `${#name}`: the length of the value.
+    Length,
+    /// `${name#word}`, or `##` for the longest match.
+    RemovePrefix(bool),
+    /// `${name%word}`, or `%%` for the longest match.
+    RemoveSuffix(bool),
+    /// `${name/pattern/word}`, or `//` to replace every match.
+    Replace(bool)
+}
+
+impl ExpansionOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExpansionOp::Default(true) => ":-",
+            ExpansionOp::Default(false) => "-",
+            ExpansionOp::Assign(true) => ":=",
+            ExpansionOp::Assign(false) => "=",
+            ExpansionOp::Error(true) => ":?",
+            ExpansionOp::Error(false) => "?",
+            ExpansionOp::Length => "#",
+            ExpansionOp::RemovePrefix(false) => "#",
+            ExpansionOp::RemovePrefix(true) => "##",
+            ExpansionOp::RemoveSuffix(false) => "%",
+            ExpansionOp::RemoveSuffix(true) => "%%",
+            ExpansionOp::Replace(false) => "/",
+            ExpansionOp::Replace(true) => "//"
+        }
+    }
+}
+
+/// A redirection of one of a command's file descriptors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `N<file`, reading from the file.
+    Input(i32),
+    /// `N>file`, truncating the file.
+    Output(i32),
+    /// `N>>file`, appending to the file.
+    Append(i32),
+    /// `N>&M`, making N a copy of M.
+    Duplicate(i32, i32),
+    /// `&>file`, or `&>>file` to append, sending both stdout and stderr to
+    /// the file.
+    All(bool),
+    /// `N<<DELIMITER`, reading from the lines up to the delimiter.
+    HereDoc(i32),
+    /// `N<<<word`, reading from the word and a newline.
+    HereString(i32)
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedirectOp::Input(fd) => write!(f, "{}<", fd),
+            RedirectOp::Output(fd) => write!(f, "{}>", fd),
+            RedirectOp::Append(fd) => write!(f, "{}>>", fd),
+            RedirectOp::Duplicate(fd, source) => write!(f, "{}>&{}", fd, source),
+            RedirectOp::All(false) => write!(f, "&>"),
+            RedirectOp::All(true) => write!(f, "&>>"),
+            RedirectOp::HereDoc(fd) => write!(f, "{}<<", fd),
+            RedirectOp::HereString(fd) => write!(f, "{}<<<", fd)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Token {
     Keyword(String),
     NumberLiteral(f64),
     StringLiteral(String),
+    RawString(String),
     BoolLiteral(bool),
+    VarRef(String),
+    /// A `${...}` expansion with an operator, holding the variable name, the
+    /// operator and its words, each with the position its text starts at.
+    ParamExpansion(String, ExpansionOp, Vec<(String, usize)>),
     Identifier(String),
     BinaryOp(String),
     LogicalOp(String),
@@ -15,28 +130,45 @@ pub enum Token {
     CloseParen,
     OpenBrace,
     CloseBrace,
+    Comma,
+    /// A `;` or newline ending a statement.
+    Separator(char),
     Pipe,
+    /// `|&`, which pipes stderr as well as stdout.
+    PipeAll,
+    Redirect(RedirectOp),
+    /// A here-document with the file descriptor it's for, its lines, each
+    /// with the position its text starts at, and whether variables in it
+    /// are expanded.
+    HereDoc(i32, Vec<(String, usize)>, bool),
     Empty
 }
 
 impl Token {
-    pub fn to_string(&self) -> String {
+    /// A short description of the kind of token, for error messages.
+    pub fn kind(&self) -> &'static str {
         match self {
-            Token::Keyword(s) => String::from(format!("Keyword ({})", s)),
-            Token::NumberLiteral(f) => String::from(format!("NumberLiteral ({})", f)),
-            Token::StringLiteral(s) => String::from(format!("StringLiteral ({})", s)),
-            Token::BoolLiteral(b) => String::from(format!("BoolLiteral ({})", b)),
-            Token::Identifier(s) => String::from(format!("Identifier ({})", s)),
-            Token::BinaryOp(s) => String::from(format!("BinaryOp ({})", s)),
-            Token::LogicalOp(s) => String::from(format!("LogicalOp ({})", s)),
-            Token::AssignmentOp(s) => String::from(format!("AssignmentOp ({})", s)),
-            Token::Let => String::from("Let"),
-            Token::OpenParen => String::from("("),
-            Token::CloseParen => String::from(")"),
-            Token::OpenBrace => String::from("{"),
-            Token::CloseBrace => String::from("}"),
-            Token::Pipe => String::from("|"),
-            Token::Empty => String::from("None")
+            Token::Keyword(_) => "keyword",
+            Token::NumberLiteral(_) => "number",
+            Token::StringLiteral(_) | Token::RawString(_) => "string",
+            Token::VarRef(_) | Token::ParamExpansion(..) => "variable",
+            Token::BoolLiteral(_) => "boolean",
+            Token::Identifier(_) => "identifier",
+            Token::BinaryOp(_) => "operator",
+            Token::LogicalOp(_) => "logical operator",
+            Token::AssignmentOp(_) => "assignment operator",
+            Token::Let => "`let`",
+            Token::OpenParen => "`(`",
+            Token::CloseParen => "`)`",
+            Token::OpenBrace => "`{`",
+            Token::CloseBrace => "`}`",
+            Token::Comma => "`,`",
+            Token::Separator(_) => "separator",
+            Token::Pipe => "`|`",
+            Token::PipeAll => "`|&`",
+            Token::Redirect(_) => "redirection",
+            Token::HereDoc(..) => "here-document",
+            Token::Empty => "end of input"
         }
     }
 }
@@ -44,46 +176,42 @@ impl Token {
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let tok = match self {
-            Token::Keyword(s) => String::from(format!("{}", s)),
-            Token::NumberLiteral(f) => String::from(format!("NumberLiteral ({})", f)),
-            Token::StringLiteral(s) => String::from(format!("{}", s)),
-            Token::BoolLiteral(b) => String::from(format!("{}", b)),
-            Token::Identifier(s) => String::from(format!("{}", s)),
-            Token::BinaryOp(s) => String::from(format!("{}", s)),
-            Token::LogicalOp(s) => String::from(format!("{}", s)),
-            Token::AssignmentOp(s) => String::from(format!("{}", s)),
-            Token::Let => String::from("Let"),
+            Token::Keyword(s) => s.to_string(),
+            Token::NumberLiteral(f) => f.to_string(),
+            Token::StringLiteral(s) => format!("\"{}\"", s),
+            Token::RawString(s) => format!("'{}'", s),
+            Token::VarRef(s) => format!("${}", s),
+            Token::ParamExpansion(name, ExpansionOp::Length, _) => format!("${{#{}}}", name),
+            Token::ParamExpansion(name, op, words) => {
+                let words: Vec<&str> = words.iter().map(|(word, _)| word.as_str()).collect();
+                format!("${{{}{}{}}}", name, op.as_str(), words.join("/"))
+            },
+            Token::BoolLiteral(b) => b.to_string(),
+            Token::Identifier(s) => s.to_string(),
+            Token::BinaryOp(s) => s.to_string(),
+            Token::LogicalOp(s) => s.to_string(),
+            Token::AssignmentOp(s) => s.to_string(),
+            Token::Let => String::from("let"),
             Token::OpenParen => String::from("("),
             Token::CloseParen => String::from(")"),
             Token::OpenBrace => String::from("{"),
             Token::CloseBrace => String::from("}"),
+            Token::Comma => String::from(","),
+            Token::Separator(c) => c.escape_default().to_string(),
             Token::Pipe => String::from("|"),
+            Token::PipeAll => String::from("|&"),
+            Token::Redirect(op) => op.to_string(),
+            Token::HereDoc(fd, _, _) => RedirectOp::HereDoc(*fd).to_string(),
             Token::Empty => String::from("None")
         };
-        write!(f, "Unexpected token {}", tok)
+        write!(f, "{}", tok)
     }
 }
 
 impl PartialEq for Token {
+    /// Tokens compare by kind only, so the parser can `expect` a kind of
+    /// token without knowing its value in advance.
     fn eq(&self, other: &Self) -> bool {
-        use Token::*;
-        match (self, other) {
-            (Keyword(_), Keyword(_)) => true,
-            (NumberLiteral(_), NumberLiteral(_)) => true,
-            (StringLiteral(_), StringLiteral(_)) => true,
-            (BoolLiteral(_), BoolLiteral(_)) => true,
-            (Identifier(_), Identifier(_)) => true,
-            (BinaryOp(_), BinaryOp(_)) => true,
-            (LogicalOp(_), LogicalOp(_)) => true,
-            (AssignmentOp(_), AssignmentOp(_)) => true,
-            (Let, Let) => true,
-            (OpenParen, OpenParen) => true,
-            (CloseParen, CloseParen) => true,
-            (OpenBrace, OpenBrace) => true,
-            (CloseBrace, CloseBrace) => true,
-            (Pipe, Pipe) => true,
-            (Empty, Empty) => true,
-            _ => false,
-        }
+        std::mem::discriminant(self) == std::mem::discriminant(other)
     }
-}
\ No newline at end of file
+}